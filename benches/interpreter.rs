@@ -0,0 +1,68 @@
+//! Times the programs in `example/` through `Interpreter` for each cell type,
+//! so that a change to `Memory` shows what it costs the u8 path. Run with
+//! `cargo bench`, optionally followed by a filter such as `cargo bench -- u8`.
+
+use brainfuck_hcy::{CellType, Config, InputMode, Interpreter};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 3;
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Best of `RUNS` wall times of `name.bf`, fed `tests/golden/<name>.in`.
+fn time(name: &str, cell: &str, optimize: bool) -> Duration {
+    let code = fs::read_to_string(root().join("example").join(format!("{name}.bf"))).unwrap();
+    let input_path = root()
+        .join("tests")
+        .join("golden")
+        .join(format!("{name}.in"));
+    let input = fs::read(input_path).unwrap_or_default();
+    (0..RUNS)
+        .map(|_| {
+            let mut config = Config::new();
+            config.raw_code = code.clone();
+            config.input_mode = InputMode::Raw;
+            config.optimize = optimize;
+            (config.cell_max, config.cell_type) = match cell {
+                "u8" => (u8::MAX as u32, CellType::U32),
+                "u64" => (u32::MAX, CellType::U64),
+                _ => (u32::MAX, CellType::Big),
+            };
+            let start = Instant::now();
+            Interpreter::with_config(config)
+                .input(input.as_slice())
+                .output(std::io::sink())
+                .run()
+                .unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    let benches = [
+        ("hanoi", "u8", false),
+        ("hanoi", "u8", true),
+        ("mandel", "u8", true),
+        ("mandel", "u64", true),
+        ("yapi", "u8", true),
+        ("yapi", "u64", true),
+        ("yapi", "big", true),
+    ];
+    for (name, cell, optimize) in benches {
+        let label = match optimize {
+            true => format!("{name} {cell}"),
+            false => format!("{name} {cell} --no-opt"),
+        };
+        if filter.as_ref().is_some_and(|f| !label.contains(f.as_str())) {
+            continue;
+        }
+        println!("{label:<24} {:>10.3?}", time(name, cell, optimize));
+    }
+}
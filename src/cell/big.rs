@@ -0,0 +1,160 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::AddAssign;
+
+/// Arbitrary-precision signed integer, just enough for tape cells.
+///
+/// Stored as sign and magnitude, the magnitude in little-endian 32-bit limbs
+/// without trailing zeros, so zero is always `{ negative: false, [] }` and
+/// the derived `PartialEq` compares values.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+impl BigInt {
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        let magnitude = match self.magnitude.as_slice() {
+            [] => 0,
+            [a] => *a as i128,
+            [a, b] => *a as i128 | (*b as i128) << 32,
+            _ => return None,
+        };
+        i64::try_from(if self.negative { -magnitude } else { magnitude }).ok()
+    }
+
+    pub fn low_byte(&self) -> u8 {
+        let byte = self.magnitude.first().copied().unwrap_or(0) as u8;
+        if self.negative {
+            byte.wrapping_neg()
+        } else {
+            byte
+        }
+    }
+
+    pub fn mul_small(&self, factor: i64) -> BigInt {
+        let m = factor.unsigned_abs();
+        let mut magnitude = Vec::with_capacity(self.magnitude.len() + 2);
+        let mut carry: u128 = 0;
+        for &limb in &self.magnitude {
+            let product = limb as u128 * m as u128 + carry;
+            magnitude.push(product as u32);
+            carry = product >> 32;
+        }
+        while carry != 0 {
+            magnitude.push(carry as u32);
+            carry >>= 32;
+        }
+        BigInt::new(self.negative != (factor < 0), magnitude)
+    }
+
+    fn new(negative: bool, mut magnitude: Vec<u32>) -> BigInt {
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+        BigInt {
+            negative: negative && !magnitude.is_empty(),
+            magnitude,
+        }
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> BigInt {
+        let m = n.unsigned_abs();
+        BigInt::new(n < 0, vec![m as u32, (m >> 32) as u32])
+    }
+}
+
+impl AddAssign<&BigInt> for BigInt {
+    fn add_assign(&mut self, other: &BigInt) {
+        if self.negative == other.negative {
+            self.magnitude = add_magnitude(&self.magnitude, &other.magnitude);
+            return;
+        }
+        *self = match compare_magnitude(&self.magnitude, &other.magnitude) {
+            Ordering::Less => BigInt::new(
+                other.negative,
+                sub_magnitude(&other.magnitude, &self.magnitude),
+            ),
+            _ => BigInt::new(
+                self.negative,
+                sub_magnitude(&self.magnitude, &other.magnitude),
+            ),
+        };
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // Base 10^9 chunks, least significant first
+        let mut chunks = Vec::new();
+        let mut magnitude = self.magnitude.clone();
+        while !magnitude.is_empty() {
+            let mut remainder: u64 = 0;
+            for limb in magnitude.iter_mut().rev() {
+                let current = remainder << 32 | *limb as u64;
+                *limb = (current / 1_000_000_000) as u32;
+                remainder = current % 1_000_000_000;
+            }
+            chunks.push(remainder as u32);
+            while magnitude.last() == Some(&0) {
+                magnitude.pop();
+            }
+        }
+        let mut s = String::new();
+        if self.negative {
+            s.push('-');
+        }
+        s.push_str(&chunks.pop().unwrap().to_string());
+        for chunk in chunks.iter().rev() {
+            s.push_str(&format!("{:09}", chunk));
+        }
+        write!(f, "{}", s)
+    }
+}
+
+fn compare_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &limb) in long.iter().enumerate() {
+        let sum = limb as u64 + short.get(i).copied().unwrap_or(0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry != 0 {
+        result.push(carry as u32);
+    }
+    result
+}
+
+/// `a - b`, expects `a >= b`.
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &limb) in a.iter().enumerate() {
+        let mut diff = limb as i64 - b.get(i).copied().unwrap_or(0) as i64 - borrow;
+        borrow = 0;
+        if diff < 0 {
+            diff += 1 << 32;
+            borrow = 1;
+        }
+        result.push(diff as u32);
+    }
+    result
+}
@@ -0,0 +1,305 @@
+mod big;
+
+pub use big::BigInt;
+
+use crate::{Cells, Overflow};
+use std::fmt;
+
+/// A cell value went past 0 or past the maximum under `Overflow::Trap`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflowed {
+    Above,
+    Below,
+}
+
+/// Value stored in one cell of `Memory`.
+///
+/// `max` is the largest value a cell may hold. `u32` serves the u8, u16 and
+/// u32 cell sizes through it, `BigInt` ignores it. On error a cell is left
+/// unchanged.
+pub trait Cell: Clone + PartialEq + Default + fmt::Display {
+    /// Whether the cell has a maximum, so that `Overflow::Wrap` applies.
+    const BOUNDED: bool;
+
+    fn is_zero(&self) -> bool;
+
+    /// Adds `delta` according to `overflow`.
+    fn add(&mut self, delta: i64, max: &Self, overflow: Overflow) -> Result<(), Overflowed>;
+
+    /// Adds `v * factor` according to `overflow`.
+    fn add_product(
+        &mut self,
+        v: &Self,
+        factor: i32,
+        max: &Self,
+        overflow: Overflow,
+    ) -> Result<(), Overflowed>;
+
+    /// `None` when `n` is greater than `max`.
+    fn from_u32(n: u32, max: &Self) -> Option<Self>;
+
+    /// `n` as the largest value of a cell, `None` for unbounded cells.
+    fn max_from_u32(n: u32) -> Option<Self>;
+
+    /// Parses a value written with `Display`, `None` unless it is a value up
+    /// to `max`.
+    fn parse(s: &str, max: &Self) -> Option<Self>;
+
+    /// Name of the cell size as given to `--cell`.
+    fn size_name(max: &Self) -> &'static str;
+
+    /// `None` when the value doesn't fit in a `u32`.
+    fn to_u32(&self) -> Option<u32>;
+
+    /// The low 8 bits, in two's complement for negative values.
+    fn low_byte(&self) -> u8;
+
+    /// What `-1` is stored as: `max` for unsigned cells.
+    fn minus_one(max: &Self) -> Self;
+
+    /// Formats the value as two's complement signed, unsigned cells above
+    /// `max / 2` being negative.
+    fn to_signed_string(&self, max: &Self) -> String;
+
+    fn into_cells(view: Vec<Self>) -> Cells;
+}
+
+/// Slow path of the bounded cells, once `v + delta` left `0..=max`.
+#[cold]
+fn bounded_overflow(
+    v: i128,
+    delta: i128,
+    max: i128,
+    overflow: Overflow,
+) -> Result<i128, Overflowed> {
+    match overflow {
+        Overflow::Wrap => Ok((v + delta.rem_euclid(max + 1)) % (max + 1)),
+        Overflow::Saturate => Ok((v + delta).clamp(0, max)),
+        Overflow::Trap if v + delta > max => Err(Overflowed::Above),
+        Overflow::Trap => Err(Overflowed::Below),
+    }
+}
+
+impl Cell for u32 {
+    const BOUNDED: bool = true;
+
+    fn is_zero(&self) -> bool {
+        *self == 0
+    }
+
+    #[inline(always)]
+    fn add(&mut self, delta: i64, max: &u32, overflow: Overflow) -> Result<(), Overflowed> {
+        let result = (*self as i64).saturating_add(delta);
+        if 0 <= result && result <= *max as i64 {
+            *self = result as u32;
+            return Ok(());
+        }
+        *self = bounded_overflow(*self as i128, delta as i128, *max as i128, overflow)? as u32;
+        Ok(())
+    }
+
+    fn add_product(
+        &mut self,
+        v: &u32,
+        factor: i32,
+        max: &u32,
+        overflow: Overflow,
+    ) -> Result<(), Overflowed> {
+        // |v * factor| < 2^63
+        self.add(*v as i64 * factor as i64, max, overflow)
+    }
+
+    fn from_u32(n: u32, max: &u32) -> Option<u32> {
+        (n <= *max).then_some(n)
+    }
+
+    fn max_from_u32(n: u32) -> Option<u32> {
+        Some(n)
+    }
+
+    fn parse(s: &str, max: &u32) -> Option<u32> {
+        s.parse().ok().filter(|n| n <= max)
+    }
+
+    fn size_name(max: &u32) -> &'static str {
+        match *max {
+            255 => "u8",
+            65535 => "u16",
+            _ => "u32",
+        }
+    }
+
+    fn to_u32(&self) -> Option<u32> {
+        Some(*self)
+    }
+
+    fn low_byte(&self) -> u8 {
+        *self as u8
+    }
+
+    fn minus_one(max: &u32) -> u32 {
+        *max
+    }
+
+    fn to_signed_string(&self, max: &u32) -> String {
+        match *self > max / 2 {
+            true => (*self as i64 - *max as i64 - 1).to_string(),
+            false => self.to_string(),
+        }
+    }
+
+    fn into_cells(view: Vec<u32>) -> Cells {
+        Cells::U32(view)
+    }
+}
+
+impl Cell for u64 {
+    const BOUNDED: bool = true;
+
+    fn is_zero(&self) -> bool {
+        *self == 0
+    }
+
+    fn add(&mut self, delta: i64, max: &u64, overflow: Overflow) -> Result<(), Overflowed> {
+        add_wide(self, delta as i128, *max, overflow)
+    }
+
+    fn add_product(
+        &mut self,
+        v: &u64,
+        factor: i32,
+        max: &u64,
+        overflow: Overflow,
+    ) -> Result<(), Overflowed> {
+        add_wide(self, *v as i128 * factor as i128, *max, overflow)
+    }
+
+    fn from_u32(n: u32, max: &u64) -> Option<u64> {
+        (n as u64 <= *max).then_some(n as u64)
+    }
+
+    fn max_from_u32(n: u32) -> Option<u64> {
+        Some(n as u64)
+    }
+
+    fn parse(s: &str, max: &u64) -> Option<u64> {
+        s.parse().ok().filter(|n| n <= max)
+    }
+
+    /// `:cell` can shrink u64 cells to the sizes of u32 cells.
+    fn size_name(max: &u64) -> &'static str {
+        match *max {
+            255 => "u8",
+            65535 => "u16",
+            0xFFFF_FFFF => "u32",
+            _ => "u64",
+        }
+    }
+
+    fn to_u32(&self) -> Option<u32> {
+        u32::try_from(*self).ok()
+    }
+
+    fn low_byte(&self) -> u8 {
+        *self as u8
+    }
+
+    fn minus_one(max: &u64) -> u64 {
+        *max
+    }
+
+    fn to_signed_string(&self, max: &u64) -> String {
+        match *self > max / 2 {
+            true => (*self as i128 - *max as i128 - 1).to_string(),
+            false => self.to_string(),
+        }
+    }
+
+    fn into_cells(view: Vec<u64>) -> Cells {
+        Cells::U64(view)
+    }
+}
+
+fn add_wide(v: &mut u64, delta: i128, max: u64, overflow: Overflow) -> Result<(), Overflowed> {
+    let result = *v as i128 + delta;
+    if 0 <= result && result <= max as i128 {
+        *v = result as u64;
+        return Ok(());
+    }
+    *v = bounded_overflow(*v as i128, delta, max as i128, overflow)? as u64;
+    Ok(())
+}
+
+/// Unbounded signed cells: nothing ever overflows, so `max` and the
+/// overflow policy are ignored.
+impl Cell for BigInt {
+    const BOUNDED: bool = false;
+
+    fn is_zero(&self) -> bool {
+        BigInt::is_zero(self)
+    }
+
+    fn add(&mut self, delta: i64, _: &BigInt, _: Overflow) -> Result<(), Overflowed> {
+        *self += &BigInt::from(delta);
+        Ok(())
+    }
+
+    fn add_product(
+        &mut self,
+        v: &BigInt,
+        factor: i32,
+        _: &BigInt,
+        _: Overflow,
+    ) -> Result<(), Overflowed> {
+        *self += &v.mul_small(factor as i64);
+        Ok(())
+    }
+
+    fn from_u32(n: u32, _: &BigInt) -> Option<BigInt> {
+        Some(BigInt::from(n as i64))
+    }
+
+    fn max_from_u32(_: u32) -> Option<BigInt> {
+        None
+    }
+
+    fn parse(s: &str, _: &BigInt) -> Option<BigInt> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let mut n = BigInt::default();
+        for digit in digits.bytes() {
+            n = n.mul_small(10);
+            n += &BigInt::from((digit - b'0') as i64);
+        }
+        Some(if negative { n.mul_small(-1) } else { n })
+    }
+
+    fn size_name(_: &BigInt) -> &'static str {
+        "big"
+    }
+
+    fn to_u32(&self) -> Option<u32> {
+        self.to_i64().and_then(|n| u32::try_from(n).ok())
+    }
+
+    fn low_byte(&self) -> u8 {
+        BigInt::low_byte(self)
+    }
+
+    fn minus_one(_: &BigInt) -> BigInt {
+        BigInt::from(-1)
+    }
+
+    fn to_signed_string(&self, _: &BigInt) -> String {
+        self.to_string()
+    }
+
+    fn into_cells(view: Vec<BigInt>) -> Cells {
+        Cells::Big(view)
+    }
+}
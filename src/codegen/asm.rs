@@ -0,0 +1,460 @@
+use super::Writer;
+use crate::Token;
+
+/// Buffered output, UTF-8 encoding, input decoding and error reporting.
+/// `%rbx` holds the tape base, `%r12` the pointer and `%r13` the tape length;
+/// the helpers only clobber caller-saved registers.
+static RUNTIME: &str = r#"
+bf_flush:
+    leaq outbuf(%rip), %rsi
+    movq outlen(%rip), %rdx
+1:
+    testq %rdx, %rdx
+    jz 2f
+    movl $1, %eax
+    movl $1, %edi
+    syscall
+    testq %rax, %rax
+    jle 2f
+    addq %rax, %rsi
+    subq %rax, %rdx
+    jmp 1b
+2:
+    movq $0, outlen(%rip)
+    ret
+
+bf_putbyte:
+    movq outlen(%rip), %rax
+    cmpq $4096, %rax
+    jb 1f
+    pushq %rdi
+    call bf_flush
+    popq %rdi
+    xorl %eax, %eax
+1:
+    leaq outbuf(%rip), %rcx
+    movb %dil, (%rcx,%rax)
+    incq %rax
+    movq %rax, outlen(%rip)
+    ret
+
+bf_put:
+    movl %edi, %r8d
+    cmpl $0x80, %r8d
+    jb bf_putbyte
+    cmpl $0x800, %r8d
+    jb .Lput2
+    cmpl $0x10000, %r8d
+    jb .Lput3
+    cmpl $0x10FFFF, %r8d
+    jbe .Lput4
+    jmp bf_fail_unicode
+.Lput4:
+    movl %r8d, %edi
+    shrl $18, %edi
+    orl $0xF0, %edi
+    call bf_putbyte
+    movl %r8d, %edi
+    shrl $12, %edi
+    andl $0x3F, %edi
+    orl $0x80, %edi
+    call bf_putbyte
+    jmp .Lput_second_last
+.Lput3:
+    movl %r8d, %eax
+    andl $0xF800, %eax
+    cmpl $0xD800, %eax
+    je bf_fail_unicode
+    movl %r8d, %edi
+    shrl $12, %edi
+    orl $0xE0, %edi
+    call bf_putbyte
+.Lput_second_last:
+    movl %r8d, %edi
+    shrl $6, %edi
+    andl $0x3F, %edi
+    orl $0x80, %edi
+    call bf_putbyte
+    jmp .Lput_last
+.Lput2:
+    movl %r8d, %edi
+    shrl $6, %edi
+    orl $0xC0, %edi
+    call bf_putbyte
+.Lput_last:
+    movl %r8d, %edi
+    andl $0x3F, %edi
+    orl $0x80, %edi
+    jmp bf_putbyte
+
+bf_readbyte:
+    xorl %eax, %eax
+    xorl %edi, %edi
+    leaq inbyte(%rip), %rsi
+    movl $1, %edx
+    syscall
+    cmpq $1, %rax
+    jne 1f
+    movzbl inbyte(%rip), %eax
+    ret
+1:
+    movl $-1, %eax
+    ret
+
+bf_get:
+    call bf_flush
+    call bf_readbyte
+    testl %eax, %eax
+    js .Lget_eof
+    # A byte unless cells can hold every character, like the interpreter
+.if CELL_MAX < 0x10FFFF
+    jmp .Lget_check
+.endif
+    cmpl $0x80, %eax
+    jb .Lget_check
+    movl %eax, %ecx
+    andl $0xE0, %ecx
+    cmpl $0xC0, %ecx
+    jne 1f
+    andl $0x1F, %eax
+    movl $1, %r9d
+    jmp .Lget_more
+1:
+    movl %eax, %ecx
+    andl $0xF0, %ecx
+    cmpl $0xE0, %ecx
+    jne 2f
+    andl $0x0F, %eax
+    movl $2, %r9d
+    jmp .Lget_more
+2:
+    movl %eax, %ecx
+    andl $0xF8, %ecx
+    cmpl $0xF0, %ecx
+    jne bf_fail_utf8
+    andl $0x07, %eax
+    movl $3, %r9d
+.Lget_more:
+    movl %eax, %r8d
+.Lget_next:
+    call bf_readbyte
+    movl %eax, %ecx
+    andl $0xC0, %ecx
+    cmpl $0x80, %ecx
+    jne bf_fail_utf8
+    shll $6, %r8d
+    andl $0x3F, %eax
+    orl %eax, %r8d
+    decl %r9d
+    jnz .Lget_next
+    movl %r8d, %eax
+.Lget_check:
+    movl %eax, %r8d
+    cmpl $CELL_MAX, %eax
+    ja bf_fail_input
+    ret
+.Lget_eof:
+    xorl %eax, %eax
+    ret
+
+bf_write_err:
+    movl $1, %eax
+    movl $2, %edi
+    syscall
+    ret
+
+bf_write_err_u64:
+    leaq numbuf+20(%rip), %rsi
+    movl $10, %ecx
+1:
+    xorl %edx, %edx
+    divq %rcx
+    addb $'0', %dl
+    decq %rsi
+    movb %dl, (%rsi)
+    testq %rax, %rax
+    jnz 1b
+    leaq numbuf+20(%rip), %rdx
+    subq %rsi, %rdx
+    jmp bf_write_err
+
+.macro write_msg name
+    leaq \name(%rip), %rsi
+    movl $(\name\()_end - \name), %edx
+    call bf_write_err
+.endm
+
+bf_fail_left:
+    movq %rsi, %r14
+    call bf_flush
+    write_msg msg_left
+    movq %r12, %rax
+    call bf_write_err_u64
+    write_msg msg_left_by
+    movq %r14, %rax
+    call bf_write_err_u64
+    write_msg msg_positions
+    jmp bf_exit_error
+
+bf_fail_right:
+    call bf_flush
+    write_msg msg_right
+    jmp bf_exit_error
+
+bf_fail_unicode:
+    call bf_flush
+    write_msg msg_unicode
+    movl %r8d, %eax
+    call bf_write_err_u64
+    write_msg msg_newline
+    jmp bf_exit_error
+
+bf_fail_input:
+    call bf_flush
+    write_msg msg_input
+    movl %r8d, %eax
+    call bf_write_err_u64
+    write_msg msg_input_max
+    jmp bf_exit_error
+
+bf_fail_utf8:
+    call bf_flush
+    write_msg msg_utf8
+
+bf_exit_error:
+    movl $60, %eax
+    movl $1, %edi
+    syscall
+
+    .section .rodata
+msg_left:
+    .ascii "Error: The current pointer is at position "
+msg_left_end:
+msg_left_by:
+    .ascii " and cannot move left by "
+msg_left_by_end:
+msg_positions:
+    .ascii " positions\n"
+msg_positions_end:
+msg_right:
+    .ascii "Error: The pointer moved past the end of the tape of TAPE_LEN cells\n"
+msg_right_end:
+msg_unicode:
+    .ascii "Error: Invalid Unicode scalar value: "
+msg_unicode_end:
+msg_newline:
+    .ascii "\n"
+msg_newline_end:
+msg_input:
+    .ascii "Error: Input value "
+msg_input_end:
+msg_input_max:
+    .ascii " exceeds the maximum cell value CELL_MAX\n"
+msg_input_max_end:
+msg_utf8:
+    .ascii "Error: Invalid UTF-8 input\n"
+msg_utf8_end:
+
+    .bss
+    .lcomm outbuf, 4096
+    .lcomm outlen, 8
+    .lcomm inbyte, 1
+    .lcomm numbuf, 20
+    .lcomm tape, TAPE_BYTES
+"#;
+
+/// Translates the token vector into x86-64 GNU assembler source (AT&T syntax)
+/// with its own `_start`, using Linux syscalls for I/O, so that
+/// `as out.s -o out.o && ld out.o -o out` is enough to build it.
+///
+/// Run-length counts become immediate operands (`addb $n`), the tape is a
+/// fixed `.bss` array of `tape_len` cells, and moving left of cell 0 fails
+/// with the same message as `Memory::ptr_decrease`.
+pub fn generate(token_vec: &[Token], cell_max: u32, tape_len: u32) -> String {
+    let cell = Cell::new(cell_max);
+    let mut w = Writer::new(1);
+    w.source.push_str("# Generated by brainfuck-hcy\n");
+    w.line(".text");
+    w.line(".globl _start");
+    w.source.push_str("_start:\n");
+    w.line("leaq tape(%rip), %rbx");
+    w.line("xorl %r12d, %r12d");
+    w.line(&format!("movl ${}, %r13d", tape_len));
+
+    let mut loops = Vec::new();
+    let mut next_label = 0;
+    for &token in token_vec {
+        match token {
+            Token::PtrIncrease(n) => move_right(&mut w, n),
+            Token::PtrDecrease(n) => move_left(&mut w, n),
+            Token::DataIncrease { offset, n } => {
+                check_offset(&mut w, offset);
+                w.line(&format!(
+                    "add{} ${}, {}",
+                    cell.suffix,
+                    n & cell_max,
+                    cell.at(offset)
+                ));
+            }
+            Token::DataDecrease { offset, n } => {
+                check_offset(&mut w, offset);
+                w.line(&format!(
+                    "sub{} ${}, {}",
+                    cell.suffix,
+                    n & cell_max,
+                    cell.at(offset)
+                ));
+            }
+            Token::JumpForward(_) => {
+                loops.push(next_label);
+                w.line(&format!("cmp{} $0, {}", cell.suffix, cell.at(0)));
+                w.line(&format!("je .Lend{}", next_label));
+                w.source.push_str(&format!(".Lbody{}:\n", next_label));
+                next_label += 1;
+            }
+            Token::JumpBack(_) => {
+                let label = loops.pop().expect("brackets are balanced after parsing");
+                w.line(&format!("cmp{} $0, {}", cell.suffix, cell.at(0)));
+                w.line(&format!("jne .Lbody{}", label));
+                w.source.push_str(&format!(".Lend{}:\n", label));
+            }
+            Token::Output { offset } => {
+                check_offset(&mut w, offset);
+                w.line(&format!("{} {}, %edi", cell.load, cell.at(offset)));
+                w.line("call bf_put");
+            }
+            Token::Input { offset } => {
+                check_offset(&mut w, offset);
+                w.line("call bf_get");
+                w.line(&format!(
+                    "mov{} {}, {}",
+                    cell.suffix,
+                    cell.eax,
+                    cell.at(offset)
+                ));
+            }
+            Token::SetZero { offset } => {
+                check_offset(&mut w, offset);
+                w.line(&format!("mov{} $0, {}", cell.suffix, cell.at(offset)));
+            }
+            Token::ScanRight(n) => {
+                w.source.push_str(&format!(".Lscan{}:\n", next_label));
+                w.line(&format!("cmp{} $0, {}", cell.suffix, cell.at(0)));
+                w.line(&format!("je .Lscan_end{}", next_label));
+                move_right(&mut w, n);
+                w.line(&format!("jmp .Lscan{}", next_label));
+                w.source.push_str(&format!(".Lscan_end{}:\n", next_label));
+                next_label += 1;
+            }
+            Token::ScanLeft(n) => {
+                w.source.push_str(&format!(".Lscan{}:\n", next_label));
+                w.line(&format!("cmp{} $0, {}", cell.suffix, cell.at(0)));
+                w.line(&format!("je .Lscan_end{}", next_label));
+                move_left(&mut w, n);
+                w.line(&format!("jmp .Lscan{}", next_label));
+                w.source.push_str(&format!(".Lscan_end{}:\n", next_label));
+                next_label += 1;
+            }
+            Token::MulAdd { offset, factor } => {
+                w.line(&format!("{} {}, %eax", cell.load, cell.at(0)));
+                w.line("testl %eax, %eax");
+                w.line(&format!("je .Lmul{}", next_label));
+                check_offset(&mut w, offset);
+                w.line(&format!("imull ${}, %eax, %eax", factor));
+                w.line(&format!(
+                    "add{} {}, {}",
+                    cell.suffix,
+                    cell.eax,
+                    cell.at(offset)
+                ));
+                w.source.push_str(&format!(".Lmul{}:\n", next_label));
+                next_label += 1;
+            }
+        }
+    }
+
+    w.line("call bf_flush");
+    w.line("movl $60, %eax");
+    w.line("xorl %edi, %edi");
+    w.line("syscall");
+
+    w.source.push_str(
+        &RUNTIME
+            .replace("TAPE_BYTES", &(tape_len as u64 * cell.size).to_string())
+            .replace("TAPE_LEN", &tape_len.to_string())
+            .replace("CELL_MAX", &cell_max.to_string()),
+    );
+    w.source
+}
+
+/// Operand size suffix and addressing for one cell width.
+struct Cell {
+    size: u64,
+    suffix: char,
+    load: &'static str,
+    eax: &'static str,
+}
+
+impl Cell {
+    fn new(cell_max: u32) -> Self {
+        match cell_max {
+            255 => Cell {
+                size: 1,
+                suffix: 'b',
+                load: "movzbl",
+                eax: "%al",
+            },
+            65535 => Cell {
+                size: 2,
+                suffix: 'w',
+                load: "movzwl",
+                eax: "%ax",
+            },
+            _ => Cell {
+                size: 4,
+                suffix: 'l',
+                load: "movl",
+                eax: "%eax",
+            },
+        }
+    }
+
+    /// Memory operand of the cell at `ptr + offset`.
+    fn at(&self, offset: i32) -> String {
+        match offset {
+            0 => format!("(%rbx,%r12,{})", self.size),
+            _ => format!(
+                "{}(%rbx,%r12,{})",
+                offset as i64 * self.size as i64,
+                self.size
+            ),
+        }
+    }
+}
+
+fn move_right(w: &mut Writer, n: u32) {
+    w.line(&format!("movl ${}, %eax", n));
+    w.line("addq %rax, %r12");
+    w.line("cmpq %r13, %r12");
+    w.line("jae bf_fail_right");
+}
+
+fn move_left(w: &mut Writer, n: u32) {
+    w.line(&format!("movl ${}, %esi", n));
+    w.line("cmpq %rsi, %r12");
+    w.line("jb bf_fail_left");
+    w.line("subq %rsi, %r12");
+}
+
+/// Bounds-checks `ptr + offset`; the pointer itself is always inside the tape.
+fn check_offset(w: &mut Writer, offset: i32) {
+    if offset < 0 {
+        w.line(&format!("movl ${}, %esi", offset.unsigned_abs()));
+        w.line("cmpq %rsi, %r12");
+        w.line("jb bf_fail_left");
+    } else if offset > 0 {
+        w.line(&format!("leaq {}(%r12), %rcx", offset));
+        w.line("cmpq %r13, %rcx");
+        w.line("jae bf_fail_right");
+    }
+}
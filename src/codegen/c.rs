@@ -0,0 +1,193 @@
+use super::Writer;
+use crate::Token;
+
+static PRELUDE: &str = r#"/* Generated by brainfuck-hcy */
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef {{CELL_TYPE}} cell;
+#define CELL_MAX {{CELL_MAX}}u
+#define TAPE_LEN ((size_t){{TAPE_LEN}})
+
+static cell *tape;
+static size_t tape_len;
+static size_t ptr;
+
+static void fail(const char *format, ...) {
+    va_list args;
+    fflush(stdout);
+    fputs("Error: ", stderr);
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fputc('\n', stderr);
+    exit(1);
+}
+
+static void fail_right(size_t n) {
+    fail("The current pointer is at position %zu and cannot move right by %zu positions on a tape of %zu cells", ptr, n, TAPE_LEN);
+}
+
+static inline void move_left(size_t n) {
+    if (n > ptr) {
+        fail("The current pointer is at position %zu and cannot move left by %zu positions", ptr, n);
+    }
+    ptr -= n;
+}
+
+static inline void move_right(size_t n) {
+    if (n >= TAPE_LEN - ptr) {
+        fail_right(n);
+    }
+    ptr += n;
+}
+
+static inline cell *at(long offset) {
+    size_t index;
+    if (offset < 0) {
+        if ((size_t)-offset > ptr) {
+            fail("The current pointer is at position %zu and cannot move left by %ld positions", ptr, -offset);
+        }
+        index = ptr - (size_t)-offset;
+    } else {
+        if ((size_t)offset >= TAPE_LEN - ptr) {
+            fail_right((size_t)offset);
+        }
+        index = ptr + (size_t)offset;
+    }
+    if (index >= tape_len) {
+        size_t new_len = tape_len * 2;
+        while (new_len <= index) {
+            new_len *= 2;
+        }
+        tape = realloc(tape, new_len * sizeof(cell));
+        if (tape == NULL) {
+            fail("Out of memory");
+        }
+        memset(tape + tape_len, 0, (new_len - tape_len) * sizeof(cell));
+        tape_len = new_len;
+    }
+    return &tape[index];
+}
+
+static inline void put(uint32_t c) {
+    if (c < 0x80) {
+        putchar(c);
+    } else if (c < 0x800) {
+        putchar(0xC0 | c >> 6);
+        putchar(0x80 | (c & 0x3F));
+    } else if (c < 0x10000 && (c < 0xD800 || c > 0xDFFF)) {
+        putchar(0xE0 | c >> 12);
+        putchar(0x80 | (c >> 6 & 0x3F));
+        putchar(0x80 | (c & 0x3F));
+    } else if (c >= 0x10000 && c <= 0x10FFFF) {
+        putchar(0xF0 | c >> 18);
+        putchar(0x80 | (c >> 12 & 0x3F));
+        putchar(0x80 | (c >> 6 & 0x3F));
+        putchar(0x80 | (c & 0x3F));
+    } else {
+        fail("Invalid Unicode scalar value: %u", (unsigned)c);
+    }
+}
+
+static inline uint32_t get(void) {
+    uint32_t c;
+    int b, extra;
+    fflush(stdout);
+    b = getchar();
+    if (b == EOF) {
+        return 0;
+    }
+    /* Like the interpreter, a byte unless cells can hold every character */
+    if (CELL_MAX < 0x10FFFF) {
+        return b;
+    }
+    if (b < 0x80) {
+        c = b;
+        extra = 0;
+    } else if ((b & 0xE0) == 0xC0) {
+        c = b & 0x1F;
+        extra = 1;
+    } else if ((b & 0xF0) == 0xE0) {
+        c = b & 0x0F;
+        extra = 2;
+    } else if ((b & 0xF8) == 0xF0) {
+        c = b & 0x07;
+        extra = 3;
+    } else {
+        fail("Invalid UTF-8 input");
+    }
+    while (extra-- > 0) {
+        b = getchar();
+        if (b == EOF || (b & 0xC0) != 0x80) {
+            fail("Invalid UTF-8 input");
+        }
+        c = c << 6 | (b & 0x3F);
+    }
+    if (c > CELL_MAX) {
+        fail("Input value %u exceeds the maximum cell value %u", (unsigned)c, (unsigned)CELL_MAX);
+    }
+    return c;
+}
+
+int main(void) {
+    tape_len = 1024;
+    tape = calloc(tape_len, sizeof(cell));
+    if (tape == NULL) {
+        fail("Out of memory");
+    }
+
+"#;
+
+static EPILOGUE: &str = r#"
+    fflush(stdout);
+    return 0;
+}
+"#;
+
+/// Translates the token vector into a self-contained C99 program.
+///
+/// The cell type follows `cell_max`, so wraparound comes from unsigned
+/// integer arithmetic, and moving left of cell 0 or past the end of a tape of
+/// `fixed_len` cells fails with the same message as `Memory`.
+pub fn generate(token_vec: &[Token], cell_max: u32, fixed_len: Option<u32>) -> String {
+    let cell_type = match cell_max {
+        255 => "uint8_t",
+        65535 => "uint16_t",
+        _ => "uint32_t",
+    };
+    let mut w = Writer::new(1);
+    w.source = PRELUDE
+        .replace("{{CELL_TYPE}}", cell_type)
+        .replace("{{CELL_MAX}}", &cell_max.to_string())
+        .replace(
+            "{{TAPE_LEN}}",
+            &fixed_len.map_or("SIZE_MAX".to_string(), |len| len.to_string()),
+        );
+
+    for &token in token_vec {
+        match token {
+            Token::PtrIncrease(n) => w.line(&format!("move_right({});", n)),
+            Token::PtrDecrease(n) => w.line(&format!("move_left({});", n)),
+            Token::DataIncrease { offset, n } => w.line(&format!("*at({}) += {}u;", offset, n)),
+            Token::DataDecrease { offset, n } => w.line(&format!("*at({}) -= {}u;", offset, n)),
+            Token::JumpForward(_) => w.open("while (*at(0)) {"),
+            Token::JumpBack(_) => w.close("}"),
+            Token::Output { offset } => w.line(&format!("put(*at({}));", offset)),
+            Token::Input { offset } => w.line(&format!("*at({}) = get();", offset)),
+            Token::SetZero { offset } => w.line(&format!("*at({}) = 0;", offset)),
+            Token::ScanRight(n) => w.line(&format!("while (*at(0)) move_right({});", n)),
+            Token::ScanLeft(n) => w.line(&format!("while (*at(0)) move_left({});", n)),
+            Token::MulAdd { offset, factor } => w.line(&format!(
+                "if (*at(0)) *at({}) += (uint32_t)*at(0) * {}u;",
+                offset, factor as u32
+            )),
+        }
+    }
+
+    w.source.push_str(EPILOGUE);
+    w.source
+}
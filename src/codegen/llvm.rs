@@ -0,0 +1,568 @@
+use super::Writer;
+use crate::Token;
+
+static DECLARATIONS: &str = r#"declare i32 @putchar(i32)
+declare i32 @getchar()
+declare i32 @fflush(ptr)
+declare i32 @fprintf(ptr, ptr, ...)
+declare ptr @calloc(i64, i64)
+declare ptr @realloc(ptr, i64)
+declare void @exit(i32) noreturn
+declare void @llvm.memset.p0.i64(ptr, i8, i64, i1)
+
+@stderr = external global ptr
+@tape = internal global ptr null
+@tape_len = internal global i64 0
+"#;
+
+static RUNTIME: &str = r#"
+define internal void @bf_fail_left(i64 %ptr, i64 %n) noreturn cold noinline {
+entry:
+    %flush = call i32 @fflush(ptr null)
+    %err = load ptr, ptr @stderr
+    %print = call i32 (ptr, ptr, ...) @fprintf(ptr %err, ptr @.left, i64 %ptr, i64 %n)
+    call void @exit(i32 1)
+    unreachable
+}
+
+define internal void @bf_fail_right(i64 %ptr, i64 %n) noreturn cold noinline {
+entry:
+    %flush = call i32 @fflush(ptr null)
+    %err = load ptr, ptr @stderr
+    %print = call i32 (ptr, ptr, ...) @fprintf(ptr %err, ptr @.right, i64 %ptr, i64 %n, i64 {{TAPE_LEN}})
+    call void @exit(i32 1)
+    unreachable
+}
+
+define internal void @bf_fail_value(ptr %format, i32 %value) noreturn cold noinline {
+entry:
+    %flush = call i32 @fflush(ptr null)
+    %err = load ptr, ptr @stderr
+    %print = call i32 (ptr, ptr, ...) @fprintf(ptr %err, ptr %format, i32 %value, i32 {{CELL_MAX}})
+    call void @exit(i32 1)
+    unreachable
+}
+
+define internal void @bf_grow(i64 %index) cold noinline {
+entry:
+    %len = load i64, ptr @tape_len
+    br label %double
+double:
+    %cur = phi i64 [ %len, %entry ], [ %next, %double ]
+    %next = shl i64 %cur, 1
+    %enough = icmp ugt i64 %next, %index
+    br i1 %enough, label %alloc, label %double
+alloc:
+    %tape = load ptr, ptr @tape
+    %bytes = mul i64 %next, {{CELL_SIZE}}
+    %new = call ptr @realloc(ptr %tape, i64 %bytes)
+    %null = icmp eq ptr %new, null
+    br i1 %null, label %oom, label %clear
+oom:
+    call void @bf_fail_value(ptr @.oom, i32 0)
+    unreachable
+clear:
+    %old_bytes = mul i64 %len, {{CELL_SIZE}}
+    %start = getelementptr inbounds i8, ptr %new, i64 %old_bytes
+    %extra = sub i64 %bytes, %old_bytes
+    call void @llvm.memset.p0.i64(ptr %start, i8 0, i64 %extra, i1 false)
+    store ptr %new, ptr @tape
+    store i64 %next, ptr @tape_len
+    ret void
+}
+
+define internal ptr @bf_at(i64 %ptr, i64 %offset) alwaysinline {
+entry:
+    %index = add i64 %ptr, %offset
+    %negative = icmp slt i64 %index, 0
+    br i1 %negative, label %left, label %bounded
+left:
+    %n = sub i64 0, %offset
+    call void @bf_fail_left(i64 %ptr, i64 %n)
+    unreachable
+bounded:
+    %past = icmp uge i64 %index, {{TAPE_LEN}}
+    br i1 %past, label %right, label %inside
+right:
+    call void @bf_fail_right(i64 %ptr, i64 %offset)
+    unreachable
+inside:
+    %len = load i64, ptr @tape_len
+    %fits = icmp ult i64 %index, %len
+    br i1 %fits, label %done, label %grow
+grow:
+    call void @bf_grow(i64 %index)
+    br label %done
+done:
+    %tape = load ptr, ptr @tape
+    %cell = getelementptr inbounds {{CELL}}, ptr %tape, i64 %index
+    ret ptr %cell
+}
+
+define internal i64 @bf_move_left(i64 %ptr, i64 %n) alwaysinline {
+entry:
+    %ok = icmp uge i64 %ptr, %n
+    br i1 %ok, label %done, label %fail
+fail:
+    call void @bf_fail_left(i64 %ptr, i64 %n)
+    unreachable
+done:
+    %moved = sub i64 %ptr, %n
+    ret i64 %moved
+}
+
+define internal i64 @bf_move_right(i64 %ptr, i64 %n) alwaysinline {
+entry:
+    %room = sub i64 {{TAPE_LEN}}, %ptr
+    %ok = icmp ult i64 %n, %room
+    br i1 %ok, label %done, label %fail
+fail:
+    call void @bf_fail_right(i64 %ptr, i64 %n)
+    unreachable
+done:
+    %moved = add i64 %ptr, %n
+    ret i64 %moved
+}
+
+define internal void @bf_put(i32 %c) {
+entry:
+    %one = icmp ult i32 %c, 128
+    br i1 %one, label %emit1, label %check2
+emit1:
+    %r1 = call i32 @putchar(i32 %c)
+    ret void
+check2:
+    %two = icmp ult i32 %c, 2048
+    br i1 %two, label %emit2, label %check3
+emit2:
+    %a2 = lshr i32 %c, 6
+    %b2 = or i32 %a2, 192
+    %r2a = call i32 @putchar(i32 %b2)
+    %c2 = and i32 %c, 63
+    %d2 = or i32 %c2, 128
+    %r2b = call i32 @putchar(i32 %d2)
+    ret void
+check3:
+    %three = icmp ult i32 %c, 65536
+    br i1 %three, label %surrogate, label %check4
+surrogate:
+    %high = and i32 %c, 63488
+    %is_surrogate = icmp eq i32 %high, 55296
+    br i1 %is_surrogate, label %invalid, label %emit3
+emit3:
+    %a3 = lshr i32 %c, 12
+    %b3 = or i32 %a3, 224
+    %r3a = call i32 @putchar(i32 %b3)
+    %c3 = lshr i32 %c, 6
+    %d3 = and i32 %c3, 63
+    %e3 = or i32 %d3, 128
+    %r3b = call i32 @putchar(i32 %e3)
+    %f3 = and i32 %c, 63
+    %g3 = or i32 %f3, 128
+    %r3c = call i32 @putchar(i32 %g3)
+    ret void
+check4:
+    %four = icmp ule i32 %c, 1114111
+    br i1 %four, label %emit4, label %invalid
+emit4:
+    %a4 = lshr i32 %c, 18
+    %b4 = or i32 %a4, 240
+    %r4a = call i32 @putchar(i32 %b4)
+    %c4 = lshr i32 %c, 12
+    %d4 = and i32 %c4, 63
+    %e4 = or i32 %d4, 128
+    %r4b = call i32 @putchar(i32 %e4)
+    %f4 = lshr i32 %c, 6
+    %g4 = and i32 %f4, 63
+    %h4 = or i32 %g4, 128
+    %r4c = call i32 @putchar(i32 %h4)
+    %i4 = and i32 %c, 63
+    %j4 = or i32 %i4, 128
+    %r4d = call i32 @putchar(i32 %j4)
+    ret void
+invalid:
+    call void @bf_fail_value(ptr @.unicode, i32 %c)
+    unreachable
+}
+
+define internal i32 @bf_get() {
+entry:
+    %flush = call i32 @fflush(ptr null)
+    %b = call i32 @getchar()
+    %eof = icmp slt i32 %b, 0
+    br i1 %eof, label %zero, label %lead
+zero:
+    ret i32 0
+lead:
+    ; Like the interpreter, a byte unless cells can hold every character
+    %ascii = icmp ult i32 %b, 128
+    %narrow = icmp ult i32 {{CELL_MAX}}, 1114111
+    %byte = or i1 %ascii, %narrow
+    br i1 %byte, label %check, label %try2
+try2:
+    %m2 = and i32 %b, 224
+    %is2 = icmp eq i32 %m2, 192
+    br i1 %is2, label %lead2, label %try3
+lead2:
+    %v2 = and i32 %b, 31
+    br label %continuation
+try3:
+    %m3 = and i32 %b, 240
+    %is3 = icmp eq i32 %m3, 224
+    br i1 %is3, label %lead3, label %try4
+lead3:
+    %v3 = and i32 %b, 15
+    br label %continuation
+try4:
+    %m4 = and i32 %b, 248
+    %is4 = icmp eq i32 %m4, 240
+    br i1 %is4, label %lead4, label %bad
+lead4:
+    %v4 = and i32 %b, 7
+    br label %continuation
+continuation:
+    %v0 = phi i32 [ %v2, %lead2 ], [ %v3, %lead3 ], [ %v4, %lead4 ]
+    %n0 = phi i32 [ 1, %lead2 ], [ 2, %lead3 ], [ 3, %lead4 ]
+    br label %more
+more:
+    %v = phi i32 [ %v0, %continuation ], [ %next_v, %append ]
+    %n = phi i32 [ %n0, %continuation ], [ %next_n, %append ]
+    %left = icmp ugt i32 %n, 0
+    br i1 %left, label %read, label %check
+read:
+    %cb = call i32 @getchar()
+    %top = and i32 %cb, 192
+    %valid = icmp eq i32 %top, 128
+    br i1 %valid, label %append, label %bad
+append:
+    %low = and i32 %cb, 63
+    %shifted = shl i32 %v, 6
+    %next_v = or i32 %shifted, %low
+    %next_n = sub i32 %n, 1
+    br label %more
+check:
+    %value = phi i32 [ %b, %lead ], [ %v, %more ]
+    %too_big = icmp ugt i32 %value, {{CELL_MAX}}
+    br i1 %too_big, label %overflow, label %done
+done:
+    ret i32 %value
+overflow:
+    call void @bf_fail_value(ptr @.input, i32 %value)
+    unreachable
+bad:
+    call void @bf_fail_value(ptr @.utf8, i32 0)
+    unreachable
+}
+
+define i32 @main() {
+entry:
+    %ptr.addr = alloca i64
+    store i64 0, ptr %ptr.addr
+    %init = call ptr @calloc(i64 1024, i64 {{CELL_SIZE}})
+    %init_null = icmp eq ptr %init, null
+    br i1 %init_null, label %init_oom, label %start
+init_oom:
+    call void @bf_fail_value(ptr @.oom, i32 0)
+    unreachable
+start:
+    store ptr %init, ptr @tape
+    store i64 1024, ptr @tape_len
+"#;
+
+static EPILOGUE: &str = r#"    %exit_flush = call i32 @fflush(ptr null)
+    ret i32 0
+}
+"#;
+
+/// Translates the token vector into a textual LLVM IR module with a `main`
+/// function, for `clang` or `llc` to build.
+///
+/// Every loop becomes a `cond` / `body` / `end` triple of basic blocks. Cells
+/// are `i8`, `i16` or `i32` according to `cell_max`, the tape grows on demand
+/// up to `fixed_len` cells, and moving left of cell 0 or past the end fails
+/// like `Memory`.
+pub fn generate(token_vec: &[Token], cell_max: u32, fixed_len: Option<u32>) -> String {
+    let (cell, cell_size) = match cell_max {
+        255 => ("i8", 1),
+        65535 => ("i16", 2),
+        _ => ("i32", 4),
+    };
+    let mut f = Function {
+        w: Writer::new(1),
+        cell,
+        cell_max,
+        next_value: 0,
+    };
+
+    f.w.source.push_str("; Generated by brainfuck-hcy\n");
+    f.w.source.push_str(DECLARATIONS);
+    for (name, text) in [
+        (
+            "left",
+            "Error: The current pointer is at position %llu and cannot move left by %llu positions\n",
+        ),
+        (
+            "right",
+            "Error: The current pointer is at position %llu and cannot move right by %llu positions on a tape of %llu cells\n",
+        ),
+        ("unicode", "Error: Invalid Unicode scalar value: %u\n"),
+        (
+            "input",
+            "Error: Input value %u exceeds the maximum cell value %u\n",
+        ),
+        ("utf8", "Error: Invalid UTF-8 input\n"),
+        ("oom", "Error: Out of memory\n"),
+    ] {
+        f.w.source.push_str(&string_constant(name, text));
+    }
+    f.w.source.push_str(
+        &RUNTIME
+            .replace("{{CELL}}", cell)
+            .replace("{{CELL_SIZE}}", &cell_size.to_string())
+            .replace("{{CELL_MAX}}", &cell_max.to_string())
+            // Unsigned, -1 is past any index of a growing tape
+            .replace("{{TAPE_LEN}}", &fixed_len.map_or(-1, i64::from).to_string()),
+    );
+
+    let mut loops = Vec::new();
+    for &token in token_vec {
+        match token {
+            Token::PtrIncrease(n) => {
+                let ptr = f.load_ptr();
+                let moved = f.value();
+                f.w.line(&format!(
+                    "{} = call i64 @bf_move_right(i64 {}, i64 {})",
+                    moved, ptr, n
+                ));
+                f.store_ptr(&moved);
+            }
+            Token::PtrDecrease(n) => {
+                let ptr = f.load_ptr();
+                let moved = f.value();
+                f.w.line(&format!(
+                    "{} = call i64 @bf_move_left(i64 {}, i64 {})",
+                    moved, ptr, n
+                ));
+                f.store_ptr(&moved);
+            }
+            Token::DataIncrease { offset, n } => f.update(offset, "add", n),
+            Token::DataDecrease { offset, n } => f.update(offset, "sub", n),
+            Token::JumpForward(_) => {
+                let label = f.next_value;
+                f.next_value += 1;
+                loops.push(label);
+                f.loop_cond(&format!("loop{}", label));
+            }
+            Token::JumpBack(_) => {
+                let label = loops.pop().expect("brackets are balanced after parsing");
+                f.w.line(&format!("br label %loop{}.cond", label));
+                f.label(&format!("loop{}.end", label));
+            }
+            Token::Output { offset } => {
+                let (_, value) = f.load_cell(offset);
+                let wide = f.widen(&value);
+                f.w.line(&format!("call void @bf_put(i32 {})", wide));
+            }
+            Token::Input { offset } => {
+                let input = f.value();
+                f.w.line(&format!("{} = call i32 @bf_get()", input));
+                let value = f.narrow(&input);
+                let cell_ptr = f.cell_ptr(offset);
+                f.w.line(&format!("store {} {}, ptr {}", cell, value, cell_ptr));
+            }
+            Token::SetZero { offset } => {
+                let cell_ptr = f.cell_ptr(offset);
+                f.w.line(&format!("store {} 0, ptr {}", cell, cell_ptr));
+            }
+            Token::ScanRight(n) => {
+                let label = format!("scan{}", f.next_value);
+                f.next_value += 1;
+                f.loop_cond(&label);
+                let ptr = f.load_ptr();
+                let moved = f.value();
+                f.w.line(&format!(
+                    "{} = call i64 @bf_move_right(i64 {}, i64 {})",
+                    moved, ptr, n
+                ));
+                f.store_ptr(&moved);
+                f.loop_end(&label);
+            }
+            Token::ScanLeft(n) => {
+                let label = format!("scan{}", f.next_value);
+                f.next_value += 1;
+                f.loop_cond(&label);
+                let ptr = f.load_ptr();
+                let moved = f.value();
+                f.w.line(&format!(
+                    "{} = call i64 @bf_move_left(i64 {}, i64 {})",
+                    moved, ptr, n
+                ));
+                f.store_ptr(&moved);
+                f.loop_end(&label);
+            }
+            Token::MulAdd { offset, factor } => {
+                let label = format!("mul{}", f.next_value);
+                f.next_value += 1;
+                let (_, source) = f.load_cell(0);
+                let nonzero = f.value();
+                f.w.line(&format!("{} = icmp ne {} {}, 0", nonzero, cell, source));
+                f.w.line(&format!(
+                    "br i1 {}, label %{}.body, label %{}.end",
+                    nonzero, label, label
+                ));
+                f.label(&format!("{}.body", label));
+                let (target_ptr, target) = f.load_cell(offset);
+                let product = f.value();
+                f.w.line(&format!(
+                    "{} = mul {} {}, {}",
+                    product,
+                    cell,
+                    source,
+                    f.constant(factor as u32)
+                ));
+                let sum = f.value();
+                f.w.line(&format!("{} = add {} {}, {}", sum, cell, target, product));
+                f.w.line(&format!("store {} {}, ptr {}", cell, sum, target_ptr));
+                f.w.line(&format!("br label %{}.end", label));
+                f.label(&format!("{}.end", label));
+            }
+        }
+    }
+
+    f.w.source.push_str(EPILOGUE);
+    f.w.source
+}
+
+/// Emits the body of `main`, handing out fresh SSA value names.
+struct Function {
+    w: Writer,
+    cell: &'static str,
+    cell_max: u32,
+    next_value: u32,
+}
+
+impl Function {
+    fn value(&mut self) -> String {
+        self.next_value += 1;
+        format!("%v{}", self.next_value)
+    }
+
+    /// `n` reduced to the cell width and written as a signed literal.
+    fn constant(&self, n: u32) -> i64 {
+        match self.cell_max {
+            255 => n as u8 as i8 as i64,
+            65535 => n as u16 as i16 as i64,
+            _ => n as i32 as i64,
+        }
+    }
+
+    fn load_ptr(&mut self) -> String {
+        let ptr = self.value();
+        self.w.line(&format!("{} = load i64, ptr %ptr.addr", ptr));
+        ptr
+    }
+
+    fn store_ptr(&mut self, value: &str) {
+        self.w.line(&format!("store i64 {}, ptr %ptr.addr", value));
+    }
+
+    fn cell_ptr(&mut self, offset: i32) -> String {
+        let ptr = self.load_ptr();
+        let cell_ptr = self.value();
+        self.w.line(&format!(
+            "{} = call ptr @bf_at(i64 {}, i64 {})",
+            cell_ptr, ptr, offset
+        ));
+        cell_ptr
+    }
+
+    /// Returns the address and the value of the cell at `offset`.
+    fn load_cell(&mut self, offset: i32) -> (String, String) {
+        let cell_ptr = self.cell_ptr(offset);
+        let value = self.value();
+        self.w
+            .line(&format!("{} = load {}, ptr {}", value, self.cell, cell_ptr));
+        (cell_ptr, value)
+    }
+
+    fn update(&mut self, offset: i32, op: &str, n: u32) {
+        let (cell_ptr, value) = self.load_cell(offset);
+        let result = self.value();
+        self.w.line(&format!(
+            "{} = {} {} {}, {}",
+            result,
+            op,
+            self.cell,
+            value,
+            self.constant(n)
+        ));
+        self.w
+            .line(&format!("store {} {}, ptr {}", self.cell, result, cell_ptr));
+    }
+
+    fn widen(&mut self, value: &str) -> String {
+        if self.cell == "i32" {
+            return value.to_string();
+        }
+        let wide = self.value();
+        self.w
+            .line(&format!("{} = zext {} {} to i32", wide, self.cell, value));
+        wide
+    }
+
+    fn narrow(&mut self, value: &str) -> String {
+        if self.cell == "i32" {
+            return value.to_string();
+        }
+        let narrow = self.value();
+        self.w.line(&format!(
+            "{} = trunc i32 {} to {}",
+            narrow, value, self.cell
+        ));
+        narrow
+    }
+
+    /// Starts a basic block, labels sit one level left of the instructions.
+    fn label(&mut self, name: &str) {
+        self.w.indent -= 1;
+        self.w.line(&format!("{}:", name));
+        self.w.indent += 1;
+    }
+
+    /// Opens `label.cond`, which enters `label.body` while the current cell is nonzero.
+    fn loop_cond(&mut self, label: &str) {
+        self.w.line(&format!("br label %{}.cond", label));
+        self.label(&format!("{}.cond", label));
+        let (_, value) = self.load_cell(0);
+        let nonzero = self.value();
+        self.w
+            .line(&format!("{} = icmp ne {} {}, 0", nonzero, self.cell, value));
+        self.w.line(&format!(
+            "br i1 {}, label %{}.body, label %{}.end",
+            nonzero, label, label
+        ));
+        self.label(&format!("{}.body", label));
+    }
+
+    fn loop_end(&mut self, label: &str) {
+        self.w.line(&format!("br label %{}.cond", label));
+        self.label(&format!("{}.end", label));
+    }
+}
+
+/// A private NUL-terminated string constant named `@.name`.
+fn string_constant(name: &str, text: &str) -> String {
+    let mut escaped = String::new();
+    for b in text.bytes() {
+        match b {
+            b' '..=b'~' if b != b'"' && b != b'\\' => escaped.push(b as char),
+            _ => escaped.push_str(&format!("\\{:02X}", b)),
+        }
+    }
+    format!(
+        "@.{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"\n",
+        name,
+        text.len() + 1,
+        escaped
+    )
+}
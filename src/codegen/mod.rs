@@ -0,0 +1,78 @@
+mod asm;
+mod c;
+mod llvm;
+mod rust;
+mod wat;
+
+use crate::errors::MyError;
+use crate::{Tape, Token};
+use std::fs;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmitTarget {
+    C,
+    Wat,
+    Llvm,
+    Asm,
+    Rust,
+}
+
+pub fn emit(
+    target: EmitTarget,
+    token_vec: &[Token],
+    cell_max: u32,
+    tape: Tape,
+    tape_len: u32,
+    output_path: Option<&str>,
+) -> Result<(), MyError> {
+    // wat and asm always use a fixed tape, the others grow unless told not to
+    let fixed_len = match tape {
+        Tape::Fixed(len) => Some(len),
+        _ => None,
+    };
+    let source = match target {
+        EmitTarget::C => c::generate(token_vec, cell_max, fixed_len),
+        EmitTarget::Wat => wat::generate(token_vec, cell_max, tape_len),
+        EmitTarget::Llvm => llvm::generate(token_vec, cell_max, fixed_len),
+        EmitTarget::Asm => asm::generate(token_vec, cell_max, tape_len),
+        EmitTarget::Rust => rust::generate(token_vec, cell_max, fixed_len),
+    };
+    match output_path {
+        Some(path) => fs::write(path, source)?,
+        None => print!("{}", source),
+    }
+    Ok(())
+}
+
+/// Collects generated lines with indentation that follows the loop depth.
+struct Writer {
+    source: String,
+    indent: usize,
+}
+
+impl Writer {
+    fn new(indent: usize) -> Self {
+        Writer {
+            source: String::new(),
+            indent,
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.indent {
+            self.source.push_str("    ");
+        }
+        self.source.push_str(line);
+        self.source.push('\n');
+    }
+
+    fn open(&mut self, line: &str) {
+        self.line(line);
+        self.indent += 1;
+    }
+
+    fn close(&mut self, line: &str) {
+        self.indent -= 1;
+        self.line(line);
+    }
+}
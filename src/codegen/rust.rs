@@ -0,0 +1,252 @@
+use super::Writer;
+use crate::Token;
+
+static PRELUDE: &str = r#"// Generated by brainfuck-hcy
+#![allow(dead_code, unused_variables)]
+
+use std::io::{self, IsTerminal, Read, Write};
+use std::process;
+
+type Cell = {{CELL}};
+const CELL_MAX: u32 = {{CELL_MAX}};
+const TAPE_LEN: usize = {{TAPE_LEN}};
+
+struct Tape {
+    cells: Vec<Cell>,
+    ptr: usize,
+}
+
+impl Tape {
+    fn at(&mut self, offset: isize) -> &mut Cell {
+        let index = self.ptr as isize + offset;
+        if index < 0 {
+            fail(format!(
+                "The current pointer is at position {} and cannot move left by {} positions",
+                self.ptr,
+                offset.unsigned_abs()
+            ));
+        }
+        let index = index as usize;
+        if index >= TAPE_LEN {
+            self.fail_right(offset as usize);
+        }
+        if self.cells.len() <= index {
+            self.cells.resize(index + 1, 0);
+        }
+        &mut self.cells[index]
+    }
+
+    fn move_left(&mut self, n: usize) {
+        if n > self.ptr {
+            fail(format!(
+                "The current pointer is at position {} and cannot move left by {} positions",
+                self.ptr, n
+            ));
+        }
+        self.ptr -= n;
+    }
+
+    fn move_right(&mut self, n: usize) {
+        if n >= TAPE_LEN - self.ptr {
+            self.fail_right(n);
+        }
+        self.ptr += n;
+    }
+
+    fn fail_right(&self, n: usize) -> ! {
+        fail(format!(
+            "The current pointer is at position {} and cannot move right by {} positions on a tape of {} cells",
+            self.ptr, n, TAPE_LEN
+        ));
+    }
+}
+
+fn fail(message: String) -> ! {
+    let _ = io::stdout().flush();
+    eprintln!("Error: {}", message);
+    process::exit(1);
+}
+
+fn put(out: &mut impl Write, n: Cell) {
+    let c = match char::from_u32(n as u32) {
+        Some(c) => c,
+        None => {
+            let _ = out.flush();
+            fail(format!("Invalid Unicode scalar value: {}", n));
+        }
+    };
+    if let Err(e) = write!(out, "{}", c) {
+        fail(format!("IO error: {}", e));
+    }
+}
+
+/// Reads input for `,` like the interpreter: a raw byte, or a UTF-8
+/// character with u32 cells, when `raw`, otherwise a prompted line holding
+/// a single character or a number ending with `u32`. EOF reads as 0.
+fn get(out: &mut impl Write, raw: bool) -> Cell {
+    if raw {
+        if let Err(e) = out.flush() {
+            fail(format!("IO error: {}", e));
+        }
+        return get_raw();
+    }
+    if let Err(e) = write!(out, "Input:").and_then(|_| out.flush()) {
+        fail(format!("IO error: {}", e));
+    }
+    let mut s = String::new();
+    if let Err(e) = io::stdin().read_line(&mut s) {
+        fail(format!("IO error: {}", e));
+    }
+    let trimmed = s.trim();
+    let n = if trimmed.ends_with("u32") {
+        s.truncate(trimmed.len() - 3);
+        match s.parse::<u32>() {
+            Ok(n) => n,
+            Err(e) => fail(format!("Parse error: {}", e)),
+        }
+    } else {
+        let line_end = if s.ends_with("\r\n") {
+            2
+        } else if s.ends_with('\n') {
+            1
+        } else {
+            let _ = writeln!(out);
+            return 0;
+        };
+        match s.chars().count() - line_end {
+            0 => '\n' as u32,
+            1 => s.chars().next().unwrap() as u32,
+            _ => fail(
+                "The length of the input string is greater than 1, unable to parse into char"
+                    .to_string(),
+            ),
+        }
+    };
+    if n > CELL_MAX {
+        fail(format!(
+            "Input value {} exceeds the maximum cell value {}",
+            n, CELL_MAX
+        ));
+    }
+    n as Cell
+}
+
+fn get_raw() -> Cell {
+    let first = match read_byte() {
+        Some(b) => b,
+        None => return 0,
+    };
+    let len = match first {
+        _ if CELL_MAX < char::MAX as u32 => return first as Cell,
+        0x00..=0x7F => return first as Cell,
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => fail(format!("Invalid UTF-8 input byte {}", first)),
+    };
+    let mut buffer = vec![first];
+    for _ in 1..len {
+        match read_byte() {
+            Some(b) => buffer.push(b),
+            None => fail("Incomplete UTF-8 input".to_string()),
+        }
+    }
+    match std::str::from_utf8(&buffer) {
+        Ok(s) => s.chars().next().unwrap() as Cell,
+        Err(e) => fail(format!("Invalid UTF-8 input: {}", e)),
+    }
+}
+
+fn read_byte() -> Option<u8> {
+    let mut byte = [0];
+    loop {
+        match io::stdin().read(&mut byte) {
+            Ok(0) => return None,
+            Ok(_) => return Some(byte[0]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => fail(format!("IO error: {}", e)),
+        }
+    }
+}
+
+fn main() {
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    // Like `--input`'s default: prompted lines on a terminal, raw otherwise
+    let raw = !io::stdin().is_terminal();
+    let mut tape = Tape {
+        cells: vec![0],
+        ptr: 0,
+    };
+
+"#;
+
+static EPILOGUE: &str = r#"
+    if let Err(e) = out.flush() {
+        fail(format!("IO error: {}", e));
+    }
+}
+"#;
+
+/// Translates the token vector into a standalone Rust program that builds
+/// with plain `rustc`.
+///
+/// Cells are `u8`, `u16` or `u32` according to `cell_max` and use wrapping
+/// arithmetic. `.` and `,` go through `std::io` with the same encoding as
+/// `IO` and the same default input mode, raw bytes unless stdin is a
+/// terminal, so stdout matches `interpreter::run`. A tape of `fixed_len`
+/// cells fails past its end like `Memory`, otherwise the tape grows.
+pub fn generate(token_vec: &[Token], cell_max: u32, fixed_len: Option<u32>) -> String {
+    let cell = match cell_max {
+        255 => "u8",
+        65535 => "u16",
+        _ => "u32",
+    };
+    let mut w = Writer::new(1);
+    w.source = PRELUDE
+        .replace("{{CELL}}", cell)
+        .replace("{{CELL_MAX}}", &cell_max.to_string())
+        .replace(
+            "{{TAPE_LEN}}",
+            &fixed_len.map_or("usize::MAX".to_string(), |len| len.to_string()),
+        );
+
+    for &token in token_vec {
+        match token {
+            Token::PtrIncrease(n) => w.line(&format!("tape.move_right({});", n)),
+            Token::PtrDecrease(n) => w.line(&format!("tape.move_left({});", n)),
+            Token::DataIncrease { offset, n } => w.line(&format!(
+                "{{ let c = tape.at({}); *c = c.wrapping_add({}); }}",
+                offset,
+                n & cell_max
+            )),
+            Token::DataDecrease { offset, n } => w.line(&format!(
+                "{{ let c = tape.at({}); *c = c.wrapping_sub({}); }}",
+                offset,
+                n & cell_max
+            )),
+            Token::JumpForward(_) => w.open("while *tape.at(0) != 0 {"),
+            Token::JumpBack(_) => w.close("}"),
+            Token::Output { offset } => w.line(&format!("put(&mut out, *tape.at({}));", offset)),
+            Token::Input { offset } => {
+                w.line(&format!("*tape.at({}) = get(&mut out, raw);", offset))
+            }
+            Token::SetZero { offset } => w.line(&format!("*tape.at({}) = 0;", offset)),
+            Token::ScanRight(n) => {
+                w.line(&format!("while *tape.at(0) != 0 {{ tape.move_right({}); }}", n))
+            }
+            Token::ScanLeft(n) => w.line(&format!(
+                "while *tape.at(0) != 0 {{ tape.move_left({}); }}",
+                n
+            )),
+            Token::MulAdd { offset, factor } => w.line(&format!(
+                "{{ let v = *tape.at(0); if v != 0 {{ let c = tape.at({}); *c = c.wrapping_add(v.wrapping_mul({})); }} }}",
+                offset,
+                factor as u32 & cell_max
+            )),
+        }
+    }
+
+    w.source.push_str(EPILOGUE);
+    w.source
+}
@@ -0,0 +1,328 @@
+use super::Writer;
+use crate::Token;
+
+/// Translates the token vector into a WebAssembly text module.
+///
+/// The module imports `env.putchar (param i32)` and `env.getchar (result i32)`,
+/// exports its `memory` and a `run` function, and keeps `tape_len` cells at
+/// the start of linear memory. Narrow stores (`store8` / `store16`) mask each
+/// cell to `cell_max`. Moving outside the tape, or reading or writing a cell
+/// at an offset outside it, traps with `unreachable`.
+pub fn generate(token_vec: &[Token], cell_max: u32, tape_len: u32) -> String {
+    let cell = Cell::new(cell_max);
+    let tape_bytes = tape_len as u64 * cell.size as u64;
+    let pages = tape_bytes.div_ceil(65536).max(1);
+
+    let mut w = Writer::new(0);
+    w.line(";; Generated by brainfuck-hcy");
+    w.open("(module");
+    w.line("(import \"env\" \"putchar\" (func $putchar (param i32)))");
+    w.line("(import \"env\" \"getchar\" (func $getchar (result i32)))");
+    w.line(&format!("(memory (export \"memory\") {})", pages));
+    w.open("(func (export \"run\")");
+    w.line("(local $ptr i32)");
+
+    let mut loops = Vec::new();
+    let mut next_label = 0;
+    for &token in token_vec {
+        match token {
+            Token::PtrIncrease(n) => move_right(&mut w, &cell, n, tape_bytes),
+            Token::PtrDecrease(n) => move_left(&mut w, &cell, n),
+            Token::DataIncrease { offset, n } => {
+                check_offset(&mut w, &cell, offset, tape_bytes);
+                w.line(&cell.store(
+                    offset,
+                    &format!("(i32.add {} (i32.const {}))", cell.load(offset), n),
+                ));
+            }
+            Token::DataDecrease { offset, n } => {
+                check_offset(&mut w, &cell, offset, tape_bytes);
+                w.line(&cell.store(
+                    offset,
+                    &format!("(i32.sub {} (i32.const {}))", cell.load(offset), n),
+                ));
+            }
+            Token::JumpForward(_) => {
+                loops.push(next_label);
+                w.open(&format!("(block $done{}", next_label));
+                w.open(&format!("(loop $loop{}", next_label));
+                w.line(&format!(
+                    "(br_if $done{} (i32.eqz {}))",
+                    next_label,
+                    cell.load(0)
+                ));
+                next_label += 1;
+            }
+            Token::JumpBack(_) => {
+                let label = loops.pop().expect("brackets are balanced after parsing");
+                w.line(&format!("(br $loop{})", label));
+                w.close(")");
+                w.close(")");
+            }
+            Token::Output { offset } => {
+                check_offset(&mut w, &cell, offset, tape_bytes);
+                w.line(&format!("(call $putchar {})", cell.load(offset)));
+            }
+            Token::Input { offset } => {
+                check_offset(&mut w, &cell, offset, tape_bytes);
+                w.line(&cell.store(offset, "(call $getchar)"));
+            }
+            Token::SetZero { offset } => {
+                check_offset(&mut w, &cell, offset, tape_bytes);
+                w.line(&cell.store(offset, "(i32.const 0)"));
+            }
+            Token::ScanRight(n) => {
+                w.open("(block");
+                w.open("(loop");
+                w.line(&format!("(br_if 1 (i32.eqz {}))", cell.load(0)));
+                move_right(&mut w, &cell, n, tape_bytes);
+                w.line("(br 0)");
+                w.close(")");
+                w.close(")");
+            }
+            Token::ScanLeft(n) => {
+                w.open("(block");
+                w.open("(loop");
+                w.line(&format!("(br_if 1 (i32.eqz {}))", cell.load(0)));
+                move_left(&mut w, &cell, n);
+                w.line("(br 0)");
+                w.close(")");
+                w.close(")");
+            }
+            Token::MulAdd { offset, factor } => {
+                w.open(&format!("(if {}", cell.load(0)));
+                w.open("(then");
+                check_offset(&mut w, &cell, offset, tape_bytes);
+                w.line(&cell.store(
+                    offset,
+                    &format!(
+                        "(i32.add {} (i32.mul {} (i32.const {})))",
+                        cell.load(offset),
+                        cell.load(0),
+                        factor
+                    ),
+                ));
+                w.close(")");
+                w.close(")");
+            }
+        }
+    }
+
+    w.close(")");
+    w.close(")");
+    w.source
+}
+
+/// Load and store instructions for one cell width.
+struct Cell {
+    size: u32,
+    load: &'static str,
+    store: &'static str,
+}
+
+impl Cell {
+    fn new(cell_max: u32) -> Self {
+        match cell_max {
+            255 => Cell {
+                size: 1,
+                load: "i32.load8_u",
+                store: "i32.store8",
+            },
+            65535 => Cell {
+                size: 2,
+                load: "i32.load16_u",
+                store: "i32.store16",
+            },
+            _ => Cell {
+                size: 4,
+                load: "i32.load",
+                store: "i32.store",
+            },
+        }
+    }
+
+    /// Byte address of the cell at `ptr + offset`.
+    fn address(&self, offset: i32) -> String {
+        match offset {
+            0 => "(local.get $ptr)".to_string(),
+            _ => format!(
+                "(i32.add (local.get $ptr) (i32.const {}))",
+                offset.wrapping_mul(self.size as i32)
+            ),
+        }
+    }
+
+    fn load(&self, offset: i32) -> String {
+        format!("({} {})", self.load, self.address(offset))
+    }
+
+    fn store(&self, offset: i32, value: &str) -> String {
+        format!("({} {} {})", self.store, self.address(offset), value)
+    }
+}
+
+fn move_right(w: &mut Writer, cell: &Cell, n: u32, tape_bytes: u64) {
+    w.line(&format!(
+        "(local.set $ptr (i32.add (local.get $ptr) (i32.const {})))",
+        n.wrapping_mul(cell.size)
+    ));
+    w.line(&format!(
+        "(if (i32.ge_u (local.get $ptr) (i32.const {})) (then unreachable))",
+        tape_bytes.min(u32::MAX as u64)
+    ));
+}
+
+fn move_left(w: &mut Writer, cell: &Cell, n: u32) {
+    let bytes = n.wrapping_mul(cell.size);
+    w.line(&format!(
+        "(if (i32.lt_u (local.get $ptr) (i32.const {})) (then unreachable))",
+        bytes
+    ));
+    w.line(&format!(
+        "(local.set $ptr (i32.sub (local.get $ptr) (i32.const {})))",
+        bytes
+    ));
+}
+
+/// Bounds-checks `ptr + offset`; the pointer itself is always inside the tape.
+fn check_offset(w: &mut Writer, cell: &Cell, offset: i32, tape_bytes: u64) {
+    let bytes = offset.unsigned_abs().wrapping_mul(cell.size);
+    if offset < 0 {
+        w.line(&format!(
+            "(if (i32.lt_u (local.get $ptr) (i32.const {})) (then unreachable))",
+            bytes
+        ));
+    } else if offset > 0 {
+        w.line(&format!(
+            "(if (i32.ge_u {} (i32.const {})) (then unreachable))",
+            cell.address(offset),
+            tape_bytes.min(u32::MAX as u64)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate;
+    use crate::optimizer::Arithmetic;
+    use crate::{compile, Tape, Token};
+    use std::fs;
+    use std::path::Path;
+
+    static INSTRUCTIONS: &[&str] = &[
+        "module",
+        "import",
+        "func",
+        "param",
+        "result",
+        "memory",
+        "export",
+        "local",
+        "local.get",
+        "local.set",
+        "i32.const",
+        "i32.add",
+        "i32.sub",
+        "i32.mul",
+        "i32.eqz",
+        "i32.ge_u",
+        "i32.lt_u",
+        "i32.load",
+        "i32.load8_u",
+        "i32.load16_u",
+        "i32.store",
+        "i32.store8",
+        "i32.store16",
+        "call",
+        "block",
+        "loop",
+        "br",
+        "br_if",
+        "if",
+        "then",
+        "unreachable",
+    ];
+
+    /// Checks that every list is balanced, starts with a known keyword and that
+    /// every `br $loopN` refers to an enclosing loop.
+    fn check_structure(source: &str) {
+        let mut depth = 0;
+        let mut labels: Vec<Option<&str>> = Vec::new();
+        let source: String = source
+            .lines()
+            .filter(|line| !line.trim_start().starts_with(";;"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let spaced = source.replace('(', " ( ").replace(')', " ) ");
+        let mut tokens = spaced.split_whitespace().peekable();
+
+        while let Some(token) = tokens.next() {
+            match token {
+                "(" => {
+                    depth += 1;
+                    let keyword = tokens.next().expect("keyword after '('");
+                    assert!(
+                        INSTRUCTIONS.contains(&keyword),
+                        "unknown instruction {keyword}"
+                    );
+                    let label = match keyword {
+                        "block" | "loop" => tokens.peek().filter(|t| t.starts_with('$')).copied(),
+                        _ => None,
+                    };
+                    labels.push(label);
+                    if keyword == "br" || keyword == "br_if" {
+                        let target = *tokens.peek().expect("branch target");
+                        if target.starts_with('$') {
+                            assert!(
+                                labels.iter().flatten().any(|l| *l == target),
+                                "branch to unknown label {target}"
+                            );
+                        }
+                    }
+                }
+                ")" => {
+                    assert!(depth > 0, "unbalanced ')'");
+                    depth -= 1;
+                    labels.pop();
+                }
+                _ => (),
+            }
+        }
+        assert_eq!(depth, 0, "unbalanced '('");
+    }
+
+    #[test]
+    fn examples_generate_well_formed_modules() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("example");
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let code = fs::read_to_string(&path).unwrap();
+            for cell_max in [255, 65535, u32::MAX] {
+                let (token_vec, _) =
+                    compile(&code, true, Arithmetic::Wrapping, Tape::Grow).unwrap();
+                let source = generate(&token_vec, cell_max, 30000);
+                assert!(source.contains("(module"), "{}", path.display());
+                assert!(source.contains("(export \"run\")"), "{}", path.display());
+                assert!(source.contains("(import \"env\" \"putchar\""));
+                assert!(source.contains("(import \"env\" \"getchar\""));
+                check_structure(&source);
+            }
+        }
+    }
+
+    #[test]
+    fn checks_cells_at_an_offset() {
+        let token_vec = [
+            Token::DataIncrease { offset: 3, n: 1 },
+            Token::Output { offset: -2 },
+        ];
+        let source = generate(&token_vec, 65535, 10);
+        assert!(source.contains(
+            "(if (i32.ge_u (i32.add (local.get $ptr) (i32.const 6)) (i32.const 20)) (then unreachable))"
+        ));
+        assert!(
+            source.contains("(if (i32.lt_u (local.get $ptr) (i32.const 4)) (then unreachable))")
+        );
+        check_structure(&source);
+    }
+}
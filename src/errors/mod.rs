@@ -1,3 +1,5 @@
+use crate::Span;
+use std::time::Duration;
 use std::{error::Error, fmt, io};
 
 #[derive(Debug)]
@@ -6,6 +8,26 @@ pub enum MyError {
     Parse(std::num::ParseIntError),
     Compile(CompileError),
     Custom(String),
+    /// A runtime error and the source span of the token that caused it.
+    At(Span, Box<MyError>),
+    /// The program ran more steps than `--max-steps` allows.
+    StepLimit(u64),
+    /// The program ran longer than `--timeout` allows.
+    Timeout(Duration),
+    /// The tape needed more cells than `--max-memory` allows.
+    MemoryLimit(usize),
+}
+
+impl MyError {
+    /// Attaches the span of the failing token, unless the error is an
+    /// IO failure that has nothing to do with the code or a limit, which
+    /// callers match on directly.
+    pub fn at(self, span: Span) -> MyError {
+        match self {
+            MyError::Io(_) | MyError::At(..) | MyError::MemoryLimit(_) => self,
+            _ => MyError::At(span, Box::new(self)),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -36,6 +58,10 @@ impl fmt::Display for MyError {
             MyError::Parse(err) => write!(f, "Parse error: {}", err),
             MyError::Compile(err) => write!(f, "Compile error: {}", err),
             MyError::Custom(err) => write!(f, "{}", err),
+            MyError::At(span, err) => write!(f, "{} at line {}", err, span),
+            MyError::StepLimit(max) => write!(f, "Step limit of {} reached", max),
+            MyError::Timeout(timeout) => write!(f, "Timed out after {:?}", timeout),
+            MyError::MemoryLimit(max) => write!(f, "Memory limit of {} cells reached", max),
         }
     }
 }
@@ -46,7 +72,11 @@ impl Error for MyError {
             MyError::Io(err) => Some(err),
             MyError::Parse(err) => Some(err),
             MyError::Compile(err) => Some(err),
-            MyError::Custom(_) => None,
+            MyError::Custom(_)
+            | MyError::StepLimit(_)
+            | MyError::Timeout(_)
+            | MyError::MemoryLimit(_) => None,
+            MyError::At(_, err) => Some(err.as_ref()),
         }
     }
 }
@@ -0,0 +1,281 @@
+use super::{exec_token, ExecQueue};
+use crate::cell::Cell;
+use crate::errors::MyError;
+use crate::{parse, Memory, Pos, Tape, Token, IO};
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+static DEBUG_HELP: &str = "Commands:
+s | step [n]         Execute the next n tokens (default 1)
+c | continue         Run until a breakpoint, a watched cell changes or the end
+b | break [line:col] Set a breakpoint on the token at line:col
+d | delete [line:col]
+                     Delete the breakpoint on the token at line:col
+w | watch [cell]     Stop when the cell changes
+m | mem [radius]     Display the cells around the pointer (default 8)
+i | info             List breakpoints and watched cells
+q | quit             Exit the debugger
+? | help             Print this
+Every '#' in the code is a breakpoint.";
+
+/// Runs the unoptimized code one token at a time, driven by commands read
+/// from stdin.
+pub fn run<C: Cell>(
+    runtime_memory: Memory<C>,
+    io: IO,
+    raw_code: &str,
+) -> Result<Memory<C>, MyError> {
+    let (token_vec, spans) = parse(raw_code, true)?;
+    let mut debugger = Debugger {
+        exec_queue: ExecQueue::new(token_vec, spans),
+        runtime_memory,
+        io,
+        breakpoints: BTreeSet::new(),
+        watches: BTreeSet::new(),
+        source: raw_code.lines().collect(),
+    };
+    debugger.break_on_hash(raw_code);
+
+    println!("{}", DEBUG_HELP);
+    println!();
+    debugger.show_location();
+    loop {
+        print!("(debug) ");
+        io::stdout().flush()?;
+
+        let mut buffer = String::new();
+        if io::stdin().read_line(&mut buffer)? == 0 {
+            println!();
+            break;
+        }
+        let mut words = buffer.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => continue,
+        };
+        let arg = words.next();
+
+        match command {
+            "s" | "step" => match arg.map(str::parse::<usize>).unwrap_or(Ok(1)) {
+                Ok(n) => debugger.advance(Some(n)),
+                Err(e) => println!("Invalid step count: {e}"),
+            },
+            "c" | "continue" => debugger.advance(None),
+            "b" | "break" | "d" | "delete" => {
+                let pos = match arg.map(parse_pos) {
+                    Some(Some(pos)) => pos,
+                    _ => {
+                        println!("Expected a position as line:col");
+                        continue;
+                    }
+                };
+                match debugger.token_at(pos) {
+                    Some(index) if command.starts_with('b') => {
+                        debugger.breakpoints.insert(index);
+                        println!("Breakpoint at {}", debugger.exec_queue.spans[index].start);
+                    }
+                    Some(index) => match debugger.breakpoints.remove(&index) {
+                        true => println!(
+                            "Deleted breakpoint at {}",
+                            debugger.exec_queue.spans[index].start
+                        ),
+                        false => println!("No breakpoint at {pos}"),
+                    },
+                    None => println!("No code at or after {pos}"),
+                }
+            }
+            "w" | "watch" => match arg.map(str::parse::<i64>) {
+                Some(Ok(cell)) => {
+                    debugger.watches.insert(cell);
+                    println!("Watching cell {cell}");
+                }
+                _ => println!("Expected a cell index"),
+            },
+            "m" | "mem" => match arg.map(str::parse::<usize>).unwrap_or(Ok(8)) {
+                Ok(radius) => debugger.show_memory(radius),
+                Err(e) => println!("Invalid radius: {e}"),
+            },
+            "i" | "info" => debugger.show_info(),
+            "q" | "quit" => break,
+            "?" | "help" => println!("{DEBUG_HELP}"),
+            _ => println!("Unknown command \"{command}\", type help for a list"),
+        }
+    }
+    Ok(debugger.runtime_memory)
+}
+
+struct Debugger<'a, C: Cell> {
+    exec_queue: ExecQueue,
+    runtime_memory: Memory<C>,
+    io: IO<'a>,
+    /// Token indices to stop before.
+    breakpoints: BTreeSet<usize>,
+    /// Cell positions to stop on when their value changes.
+    watches: BTreeSet<i64>,
+    source: Vec<&'a str>,
+}
+
+impl<C: Cell> Debugger<'_, C> {
+    fn finished(&self) -> bool {
+        self.exec_queue.ptr >= self.exec_queue.view.len()
+    }
+
+    /// Executes up to `limit` tokens, or until the end when `limit` is `None`.
+    /// Stops early on a breakpoint, a watched cell change or a runtime error.
+    fn advance(&mut self, limit: Option<usize>) {
+        let mut steps = 0;
+        let mut printed = false;
+        let mut stop = None;
+        while !self.finished() && limit != Some(steps) {
+            let before: Vec<(i64, C)> = self.watches.iter().map(|&i| (i, self.cell(i))).collect();
+            let token = self.exec_queue.next_token().unwrap();
+            printed |= matches!(token, Token::Output { .. });
+            if let Err(e) = exec_token(
+                token,
+                &mut self.exec_queue,
+                &mut self.runtime_memory,
+                &mut self.io,
+                true,
+            ) {
+                // Leave the state as it was at the failure for inspection.
+                stop = Some(format!("Error: {e}"));
+                self.exec_queue.ptr = self.exec_queue.view.len();
+                break;
+            }
+            steps += 1;
+
+            for (i, old) in before {
+                let new = self.cell(i);
+                if new != old {
+                    stop = Some(format!("Cell {i} changed from {old} to {new}"));
+                }
+            }
+            if stop.is_none() && limit.is_none() && self.breakpoints.contains(&self.exec_queue.ptr)
+            {
+                stop = Some("Breakpoint hit".to_string());
+            }
+            if stop.is_some() {
+                break;
+            }
+        }
+        // Keep the debugger messages off the program's last output line.
+        if printed {
+            println!();
+        }
+        if let Some(message) = stop {
+            println!("{message}");
+        }
+        self.show_location();
+    }
+
+    fn cell(&self, position: i64) -> C {
+        self.runtime_memory.get(position)
+    }
+
+    fn format_cell(&self, position: i64) -> String {
+        self.runtime_memory.format_cell(&self.cell(position))
+    }
+
+    /// Index of the first token that ends at or after `pos`.
+    fn token_at(&self, pos: Pos) -> Option<usize> {
+        self.exec_queue
+            .spans
+            .iter()
+            .position(|span| span.end >= pos)
+    }
+
+    fn break_on_hash(&mut self, raw_code: &str) {
+        let mut line = 1;
+        let mut col = 0;
+        for char in raw_code.chars() {
+            col += 1;
+            match char {
+                '#' => {
+                    let pos = Pos { line, col };
+                    if let Some(index) = self
+                        .exec_queue
+                        .spans
+                        .iter()
+                        .position(|span| span.start > pos)
+                    {
+                        self.breakpoints.insert(index);
+                    }
+                }
+                '\n' => {
+                    line += 1;
+                    col = 0;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Prints the next token with its source line and a caret under it.
+    fn show_location(&self) {
+        if self.finished() {
+            println!("Program finished");
+            return;
+        }
+        let index = self.exec_queue.ptr;
+        let span = self.exec_queue.spans[index];
+        println!(
+            "{} {:?}  ptr: {}, cell: {}",
+            span.start,
+            self.exec_queue.view[index],
+            self.runtime_memory.position(),
+            self.runtime_memory
+                .format_cell(self.runtime_memory.output())
+        );
+        if let Some(line) = self.source.get(span.start.line as usize - 1) {
+            let width = match span.end.line == span.start.line {
+                true => span.end.col - span.start.col + 1,
+                false => 1,
+            };
+            println!("{}", line);
+            println!(
+                "{}{}",
+                " ".repeat(span.start.col as usize - 1),
+                "^".repeat(width as usize)
+            );
+        }
+    }
+
+    fn show_memory(&self, radius: usize) {
+        let ptr = self.runtime_memory.position();
+        let radius = radius as i64;
+        let start = match self.runtime_memory.tape {
+            Tape::Grow | Tape::Fixed(_) => (ptr - radius).max(0),
+            Tape::Wrap(_) | Tape::Infinite => ptr - radius,
+        };
+        let cells: Vec<String> = (start..=ptr + radius)
+            .map(|i| match i == ptr {
+                true => format!(">{}", self.format_cell(i)),
+                false => self.format_cell(i),
+            })
+            .collect();
+        println!("{}: [{}]", start, cells.join(", "));
+    }
+
+    fn show_info(&self) {
+        if self.breakpoints.is_empty() {
+            println!("No breakpoints");
+        }
+        for &index in &self.breakpoints {
+            println!(
+                "Breakpoint at {} {:?}",
+                self.exec_queue.spans[index].start, self.exec_queue.view[index]
+            );
+        }
+        for &cell in &self.watches {
+            println!("Watching cell {} = {}", cell, self.format_cell(cell));
+        }
+    }
+}
+
+fn parse_pos(s: &str) -> Option<Pos> {
+    let (line, col) = s.split_once(':')?;
+    Some(Pos {
+        line: line.parse().ok()?,
+        col: col.parse().ok()?,
+    })
+}
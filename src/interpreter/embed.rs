@@ -0,0 +1,123 @@
+use crate::errors::MyError;
+use crate::parse_args::Config;
+use crate::{compile_config, InputMode, Outcome};
+use std::io::{self, Read, Write};
+
+/// Runs a program against any reader and writer instead of stdin and stdout.
+///
+/// Nothing is printed besides what the program writes with `.`, and `,`
+/// reads from the input without prompting.
+///
+/// ```
+/// use brainfuck_hcy::{Cells, Interpreter};
+///
+/// let mut output = Vec::new();
+/// let outcome = Interpreter::new(",[.,]")
+///     .input(&b"hello"[..])
+///     .output(&mut output)
+///     .run()
+///     .unwrap();
+/// assert_eq!(output, b"hello");
+/// assert_eq!(outcome.tape, Cells::U32(vec![0]));
+/// ```
+pub struct Interpreter<'a> {
+    config: Config,
+    input: Box<dyn Read + 'a>,
+    output: Box<dyn Write + 'a>,
+}
+
+impl<'a> Interpreter<'a> {
+    /// The defaults of the command line, except that `,` reads raw bytes.
+    /// Input starts empty and output is discarded.
+    pub fn new(code: &str) -> Self {
+        let mut config = Config::new();
+        config.raw_code = code.to_string();
+        config.input_mode = InputMode::Raw;
+        Interpreter::with_config(config)
+    }
+
+    /// Runs `config.raw_code` with the cell, tape, overflow, IO and limit
+    /// settings of `config`. REPL, debugger, profiler, verbose and `--emit`
+    /// settings are rejected by `run`.
+    pub fn with_config(config: Config) -> Self {
+        Interpreter {
+            config,
+            input: Box::new(io::empty()),
+            output: Box::new(io::sink()),
+        }
+    }
+
+    pub fn input(mut self, input: impl Read + 'a) -> Self {
+        self.input = Box::new(input);
+        self
+    }
+
+    pub fn output(mut self, output: impl Write + 'a) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Reads input from `f`, which returns `None` at the end of input.
+    pub fn input_fn(self, f: impl FnMut() -> Option<u8> + 'a) -> Self {
+        self.input(FnReader(f))
+    }
+
+    /// Passes output to `f` as it is written.
+    pub fn output_fn(self, f: impl FnMut(&[u8]) + 'a) -> Self {
+        self.output(FnWriter(f))
+    }
+
+    pub fn run(mut self) -> Result<Outcome, MyError> {
+        check_embedded(&self.config, "Interpreter")?;
+        compile_config(&mut self.config)?;
+        let io = super::io(&self.config).with_streams(self.input, self.output);
+        super::run(self.config, io)
+    }
+}
+
+/// Rejects the settings that only make sense on the command line, and the
+/// ones the JIT doesn't implement.
+pub(super) fn check_embedded(config: &Config, name: &str) -> Result<(), MyError> {
+    if config.repl_mode || config.debug || config.profile || config.verbose {
+        return Err(MyError::Custom(format!(
+            "{name} cannot run in REPL, debug, profile or verbose mode"
+        )));
+    }
+    if config.show_ir || config.emit.is_some() {
+        return Err(MyError::Custom(format!(
+            "{name} cannot show the IR or emit code"
+        )));
+    }
+    config.check_backend()
+}
+
+struct FnReader<F>(F);
+
+impl<F: FnMut() -> Option<u8>> Read for FnReader<F> {
+    /// One byte per call, so `f` is only asked for what `,` needs.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match (self.0)() {
+            Some(byte) => {
+                buf[0] = byte;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+struct FnWriter<F>(F);
+
+impl<F: FnMut(&[u8])> Write for FnWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (self.0)(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
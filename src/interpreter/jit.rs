@@ -0,0 +1,520 @@
+//! x86-64 JIT for Linux.
+//!
+//! The token vector is translated into machine code in an mmap'd page. Cells
+//! live in `Memory.view`; the generated code keeps the tape base in `r12`,
+//! the pointer in `r13`, the tape length in `r14` and the `Context` in `rbx`.
+//! Growing the tape, `.`, `,` and errors are handled by calling back into
+//! Rust, so behavior matches `normal_mode`.
+
+use super::output;
+use crate::errors::MyError;
+use crate::{Memory, OutputMode, Overflow, Span, Tape, Token, IO};
+use std::ffi::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const PROT_EXEC: i32 = 4;
+const MAP_PRIVATE: i32 = 0x02;
+const MAP_ANONYMOUS: i32 = 0x20;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: i32,
+        flags: i32,
+        fd: i32,
+        offset: i64,
+    ) -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: i32) -> i32;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+}
+
+/// State shared between the generated code and the callbacks.
+/// The first four fields are read and written by the generated code.
+#[repr(C)]
+struct Context<'a> {
+    tape: *mut u32,
+    len: u64,
+    ptr: u64,
+    /// Index of the token whose callback is running, to locate errors.
+    token: u64,
+    memory: Memory<u32>,
+    io: IO<'a>,
+    should_print_individually: bool,
+    error: Option<MyError>,
+}
+
+impl Context<'_> {
+    /// Copies the pointer from the generated code back into `memory`.
+    fn sync_in(&mut self) {
+        self.memory.ptr = self.ptr as u32;
+    }
+
+    /// Publishes the (possibly reallocated) tape to the generated code.
+    fn sync_out(&mut self) {
+        self.tape = self.memory.view.as_mut_ptr();
+        self.len = self.memory.view.len() as u64;
+        self.ptr = self.memory.ptr as u64;
+    }
+
+    fn finish(&mut self, result: Result<(), MyError>) -> u64 {
+        self.sync_out();
+        match result {
+            Ok(()) => 0,
+            Err(e) => {
+                self.error = Some(e);
+                1
+            }
+        }
+    }
+}
+
+type Callback = extern "C" fn(*mut Context<'_>, i64) -> u64;
+
+/// Runs the body of a callback. A panic must not unwind into the generated
+/// code, so it is caught and reported like any other error.
+fn callback<'a>(
+    ctx: *mut Context<'a>,
+    body: impl FnOnce(&mut Context<'a>) -> Result<(), MyError>,
+) -> u64 {
+    let ctx = unsafe { &mut *ctx };
+    ctx.sync_in();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| body(&mut *ctx)))
+        .unwrap_or_else(|_| Err(MyError::Custom("A JIT callback panicked".to_string())));
+    ctx.finish(result)
+}
+
+/// Makes the cell at index `arg` addressable.
+extern "C" fn jit_grow(ctx: *mut Context<'_>, arg: i64) -> u64 {
+    callback(ctx, |ctx| ctx.memory.grow_to(arg as usize))
+}
+
+/// Moving left by `arg` failed, let `Memory` build the error.
+extern "C" fn jit_left(ctx: *mut Context<'_>, arg: i64) -> u64 {
+    callback(ctx, |ctx| ctx.memory.ptr_decrease(arg as u32))
+}
+
+extern "C" fn jit_output(ctx: *mut Context<'_>, arg: i64) -> u64 {
+    callback(ctx, |ctx| {
+        output(
+            &mut ctx.io,
+            &mut ctx.memory,
+            arg as i32,
+            ctx.should_print_individually,
+        )
+    })
+}
+
+extern "C" fn jit_input(ctx: *mut Context<'_>, arg: i64) -> u64 {
+    callback(ctx, |ctx| ctx.io.input(&mut ctx.memory, arg as i32))
+}
+
+/// Compiles and runs `token_vec` on `runtime_memory`. Tapes other than
+/// `Tape::Grow`, overflow other than wrapping and cells narrower than their
+/// type are errors, the generated code has no way to handle them.
+pub fn run(
+    runtime_memory: Memory<u32>,
+    io: IO<'_>,
+    token_vec: &[Token],
+    spans: &[Span],
+) -> Result<Memory<u32>, MyError> {
+    if runtime_memory.tape != Tape::Grow
+        || runtime_memory.overflow != Overflow::Wrap
+        || !matches!(runtime_memory.cell_max, 255 | 65535 | 4294967295)
+    {
+        return Err(MyError::Custom(
+            "The JIT only supports wrapping u8, u16 and u32 cells on the default tape".to_string(),
+        ));
+    }
+    let code = compile(token_vec, runtime_memory.cell_max);
+    let should_print_individually = io.output_mode == OutputMode::Individually;
+    let mut ctx = Context {
+        tape: ptr::null_mut(),
+        len: 0,
+        ptr: 0,
+        token: 0,
+        memory: runtime_memory,
+        io,
+        should_print_individually,
+        error: None,
+    };
+    ctx.sync_out();
+
+    let page = ExecutablePage::new(&code)?;
+    let status = unsafe { page.call(&mut ctx) };
+    drop(page);
+
+    if status != 0 {
+        let e = match ctx.error.take() {
+            Some(e) => e.at(spans[ctx.token as usize]),
+            None => MyError::Custom("JIT code exited with an unknown error".to_string()),
+        };
+        return Err(ctx.io.failed(e));
+    }
+    if ctx.io.output_mode == OutputMode::Bulk {
+        ctx.io.write_buffer()?;
+    }
+    ctx.io.flush()?;
+    Ok(ctx.memory)
+}
+
+struct ExecutablePage {
+    addr: *mut c_void,
+    len: usize,
+}
+
+impl ExecutablePage {
+    fn new(code: &[u8]) -> Result<Self, MyError> {
+        let len = code.len().div_ceil(4096) * 4096;
+        let addr = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if addr as isize == -1 {
+            return Err(MyError::Io(std::io::Error::last_os_error()));
+        }
+        let page = ExecutablePage { addr, len };
+        unsafe {
+            ptr::copy_nonoverlapping(code.as_ptr(), addr as *mut u8, code.len());
+            if mprotect(addr, len, PROT_READ | PROT_EXEC) != 0 {
+                return Err(MyError::Io(std::io::Error::last_os_error()));
+            }
+        }
+        Ok(page)
+    }
+
+    /// # Safety
+    /// The page must hold code produced by `compile`.
+    unsafe fn call(&self, ctx: &mut Context<'_>) -> u64 {
+        let f: extern "C" fn(*mut Context<'_>) -> u64 = std::mem::transmute(self.addr);
+        f(ctx)
+    }
+}
+
+impl Drop for ExecutablePage {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.addr, self.len);
+        }
+    }
+}
+
+// Condition codes for `jcc`
+const JB: u8 = 0x82;
+const JAE: u8 = 0x83;
+const JE: u8 = 0x84;
+const JNE: u8 = 0x85;
+
+/// Argument passed in `rsi` to a callback.
+enum Arg {
+    Imm(i32),
+    Rax,
+    R13,
+}
+
+struct Assembler {
+    code: Vec<u8>,
+    /// rel32 fields that must point at the error exit
+    error_exits: Vec<usize>,
+    /// Index of the token being compiled
+    token: u32,
+}
+
+impl Assembler {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn imm32(&mut self, n: u32) {
+        self.code.extend_from_slice(&n.to_le_bytes());
+    }
+
+    /// Emits `jcc rel32` and returns the position of the rel32 field.
+    fn jcc(&mut self, cc: u8) -> usize {
+        self.emit(&[0x0F, cc]);
+        self.imm32(0);
+        self.code.len() - 4
+    }
+
+    fn jmp(&mut self) -> usize {
+        self.emit(&[0xE9]);
+        self.imm32(0);
+        self.code.len() - 4
+    }
+
+    /// Points the rel32 field at `at` to `target`.
+    fn patch(&mut self, at: usize, target: usize) {
+        let rel = target as i64 - (at as i64 + 4);
+        self.code[at..at + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+    }
+
+    fn patch_here(&mut self, at: usize) {
+        self.patch(at, self.code.len());
+    }
+
+    /// `mov eax, n` zero-extends into `rax`.
+    fn mov_eax(&mut self, n: u32) {
+        self.emit(&[0xB8]);
+        self.imm32(n);
+    }
+
+    /// Calls `callback(ctx, arg)` and leaves through the error exit if it fails.
+    fn call(&mut self, callback: Callback, arg: Arg) {
+        // mov [rbx+16], r13; mov qword [rbx+24], token
+        self.emit(&[0x4C, 0x89, 0x6B, 0x10]);
+        self.emit(&[0x48, 0xC7, 0x43, 0x18]);
+        self.imm32(self.token);
+        match arg {
+            // mov rsi, imm32 (sign-extended)
+            Arg::Imm(n) => {
+                self.emit(&[0x48, 0xC7, 0xC6]);
+                self.imm32(n as u32);
+            }
+            // mov rsi, rax
+            Arg::Rax => self.emit(&[0x48, 0x89, 0xC6]),
+            // mov rsi, r13
+            Arg::R13 => self.emit(&[0x4C, 0x89, 0xEE]),
+        }
+        // mov rdi, rbx
+        self.emit(&[0x48, 0x89, 0xDF]);
+        // mov rax, imm64; call rax
+        self.emit(&[0x48, 0xB8]);
+        self.code
+            .extend_from_slice(&(callback as usize as u64).to_le_bytes());
+        self.emit(&[0xFF, 0xD0]);
+        self.reload();
+        // test rax, rax
+        self.emit(&[0x48, 0x85, 0xC0]);
+        let at = self.jcc(JNE);
+        self.error_exits.push(at);
+    }
+
+    /// Reloads tape base, length and pointer from the context.
+    fn reload(&mut self) {
+        // mov r12, [rbx]; mov r14, [rbx+8]; mov r13, [rbx+16]
+        self.emit(&[0x4C, 0x8B, 0x63, 0x00]);
+        self.emit(&[0x4C, 0x8B, 0x73, 0x08]);
+        self.emit(&[0x4C, 0x8B, 0x6B, 0x10]);
+    }
+
+    /// Grows the tape if `r13` is past its end.
+    fn ensure_ptr(&mut self) {
+        // cmp r13, r14
+        self.emit(&[0x4D, 0x39, 0xF5]);
+        let skip = self.jcc(JB);
+        self.call(jit_grow, Arg::R13);
+        self.patch_here(skip);
+    }
+
+    /// Fails like `Memory::ptr_decrease` if `r13 < n`.
+    fn check_left(&mut self, n: u32) {
+        self.mov_eax(n);
+        // cmp r13, rax
+        self.emit(&[0x49, 0x39, 0xC5]);
+        let skip = self.jcc(JAE);
+        self.call(jit_left, Arg::Imm(n as i32));
+        self.patch_here(skip);
+    }
+
+    /// Leaves the index of the cell at `r13 + offset` in `rax`,
+    /// growing the tape when needed.
+    fn cell_index(&mut self, offset: i32) {
+        if offset == 0 {
+            // mov rax, r13
+            self.emit(&[0x4C, 0x89, 0xE8]);
+            return;
+        }
+        if offset < 0 {
+            self.check_left(offset.unsigned_abs());
+        }
+        self.lea_rax(offset);
+        if offset > 0 {
+            // cmp rax, r14
+            self.emit(&[0x4C, 0x39, 0xF0]);
+            let skip = self.jcc(JB);
+            self.call(jit_grow, Arg::Rax);
+            self.lea_rax(offset);
+            self.patch_here(skip);
+        }
+    }
+
+    /// `lea rax, [r13 + offset]`
+    fn lea_rax(&mut self, offset: i32) {
+        self.emit(&[0x49, 0x8D, 0x85]);
+        self.imm32(offset as u32);
+    }
+
+    /// `cmp dword [r12 + r13*4], 0`
+    fn cmp_current_zero(&mut self) {
+        self.emit(&[0x43, 0x83, 0x3C, 0xAC, 0x00]);
+    }
+
+    /// Applies `op ecx, n` (0x81 /ext) to the cell at index `rax`, then masks.
+    fn arith(&mut self, modrm: u8, n: u32, cell_max: u32) {
+        // mov ecx, [r12 + rax*4]
+        self.emit(&[0x41, 0x8B, 0x0C, 0x84]);
+        self.emit(&[0x81, modrm]);
+        self.imm32(n);
+        self.mask_ecx(cell_max);
+        // mov [r12 + rax*4], ecx
+        self.emit(&[0x41, 0x89, 0x0C, 0x84]);
+    }
+
+    fn mask_ecx(&mut self, cell_max: u32) {
+        if cell_max != u32::MAX {
+            // and ecx, cell_max
+            self.emit(&[0x81, 0xE1]);
+            self.imm32(cell_max);
+        }
+    }
+}
+
+fn compile(token_vec: &[Token], cell_max: u32) -> Vec<u8> {
+    let mut a = Assembler {
+        code: Vec::new(),
+        error_exits: Vec::new(),
+        token: 0,
+    };
+    // push rbp, rbx, r12, r13, r14 leaves the stack 16-byte aligned for calls
+    a.emit(&[0x55, 0x53, 0x41, 0x54, 0x41, 0x55, 0x41, 0x56]);
+    // mov rbx, rdi
+    a.emit(&[0x48, 0x89, 0xFB]);
+    a.reload();
+
+    // positions of the rel32 fields of open `[`
+    let mut stack = Vec::new();
+    for (i, &token) in token_vec.iter().enumerate() {
+        a.token = i as u32;
+        match token {
+            Token::PtrIncrease(n) => {
+                a.mov_eax(n);
+                // add r13, rax
+                a.emit(&[0x49, 0x01, 0xC5]);
+                a.ensure_ptr();
+            }
+            Token::PtrDecrease(n) => {
+                a.check_left(n);
+                // sub r13, rax
+                a.emit(&[0x49, 0x29, 0xC5]);
+            }
+            Token::DataIncrease { offset, n } => {
+                a.cell_index(offset);
+                // add ecx, imm32
+                a.arith(0xC1, n, cell_max);
+            }
+            Token::DataDecrease { offset, n } => {
+                a.cell_index(offset);
+                // sub ecx, imm32
+                a.arith(0xE9, n, cell_max);
+            }
+            Token::JumpForward(_) => {
+                a.cmp_current_zero();
+                let at = a.jcc(JE);
+                stack.push(at);
+            }
+            Token::JumpBack(_) => {
+                let at = stack.pop().expect("brackets are balanced after parsing");
+                a.cmp_current_zero();
+                let back = a.jcc(JNE);
+                a.patch(back, at + 4);
+                a.patch_here(at);
+            }
+            Token::Output { offset } => a.call(jit_output, Arg::Imm(offset)),
+            Token::Input { offset } => a.call(jit_input, Arg::Imm(offset)),
+            Token::SetZero { offset } => {
+                a.cell_index(offset);
+                // mov dword [r12 + rax*4], 0
+                a.emit(&[0x41, 0xC7, 0x04, 0x84]);
+                a.imm32(0);
+            }
+            Token::ScanRight(n) => {
+                let top = a.code.len();
+                a.cmp_current_zero();
+                let done = a.jcc(JE);
+                a.mov_eax(n);
+                // add r13, rax
+                a.emit(&[0x49, 0x01, 0xC5]);
+                a.ensure_ptr();
+                let back = a.jmp();
+                a.patch(back, top);
+                a.patch_here(done);
+            }
+            Token::ScanLeft(n) => {
+                let top = a.code.len();
+                a.cmp_current_zero();
+                let done = a.jcc(JE);
+                a.check_left(n);
+                // sub r13, rax
+                a.emit(&[0x49, 0x29, 0xC5]);
+                let back = a.jmp();
+                a.patch(back, top);
+                a.patch_here(done);
+            }
+            Token::MulAdd { offset, factor } => {
+                a.cmp_current_zero();
+                let skip = a.jcc(JE);
+                a.cell_index(offset);
+                // mov ecx, [r12 + r13*4]; imul ecx, ecx, factor
+                a.emit(&[0x43, 0x8B, 0x0C, 0xAC]);
+                a.emit(&[0x69, 0xC9]);
+                a.imm32(factor as u32);
+                // add ecx, [r12 + rax*4]
+                a.emit(&[0x41, 0x03, 0x0C, 0x84]);
+                a.mask_ecx(cell_max);
+                // mov [r12 + rax*4], ecx
+                a.emit(&[0x41, 0x89, 0x0C, 0x84]);
+                a.patch_here(skip);
+            }
+        }
+    }
+
+    // mov [rbx+16], r13; xor eax, eax
+    a.emit(&[0x4C, 0x89, 0x6B, 0x10]);
+    a.emit(&[0x31, 0xC0]);
+    let done = a.jmp();
+
+    for at in std::mem::take(&mut a.error_exits) {
+        a.patch_here(at);
+    }
+    // the callback already returned its status in rax
+    a.patch_here(done);
+    // pop r14, r13, r12, rbx, rbp; ret
+    a.emit(&[0x41, 0x5E, 0x41, 0x5D, 0x41, 0x5C, 0x5B, 0x5D, 0xC3]);
+    a.code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use crate::parse_args::Config;
+    use std::io;
+
+    fn run_code(code: &str, runtime_memory: Memory<u32>) -> Result<Memory<u32>, MyError> {
+        let (token_vec, spans) = parse(code, false).unwrap();
+        let io = crate::interpreter::io(&Config::new())
+            .with_streams(Box::new(io::empty()), Box::new(io::sink()));
+        run(runtime_memory, io, &token_vec, &spans)
+    }
+
+    #[test]
+    fn run_rejects_what_the_generated_code_cannot_do() {
+        let infinite = Memory::new(255, Tape::Infinite, Overflow::Wrap, false, None);
+        assert!(run_code("<+.", infinite).is_err());
+        let trap = Memory::new(255, Tape::Grow, Overflow::Trap, false, None);
+        assert!(run_code("-.", trap).is_err());
+        let narrow = Memory::new(100, Tape::Grow, Overflow::Wrap, false, None);
+        assert!(run_code("-.", narrow).is_err());
+        let default = Memory::new(255, Tape::Grow, Overflow::Wrap, false, None);
+        assert_eq!(run_code("-", default).unwrap().view, vec![255]);
+    }
+}
@@ -0,0 +1,502 @@
+//! Line editing for the REPL.
+//!
+//! On a Linux terminal the line is read in raw mode: arrows and the usual
+//! Emacs keys move the cursor, up and down walk the history and Ctrl-R
+//! searches it. The history is kept in `~/.brainfuck_history`. Anywhere else,
+//! or when stdin is not a terminal, lines are read as they come.
+
+use crate::errors::MyError;
+use crate::read_byte;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::PathBuf;
+
+/// Entries kept in the history file.
+const MAX_HISTORY: usize = 1000;
+
+pub enum ReadLine {
+    Line(String),
+    /// Ctrl-C, the line is discarded.
+    Interrupted,
+    Eof,
+}
+
+pub struct LineEditor {
+    history: Vec<String>,
+    /// File the history is loaded from and appended to, only when editing.
+    history_path: Option<PathBuf>,
+    raw: bool,
+}
+
+impl LineEditor {
+    pub fn new() -> LineEditor {
+        let raw = raw_mode::SUPPORTED && io::stdin().is_terminal() && io::stdout().is_terminal();
+        let history_path = match raw {
+            true => env::var_os("HOME").map(|home| PathBuf::from(home).join(".brainfuck_history")),
+            false => None,
+        };
+        let mut history: Vec<String> = history_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|s| s.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        if history.len() > MAX_HISTORY {
+            history.drain(..history.len() - MAX_HISTORY);
+            if let Some(path) = &history_path {
+                let _ = fs::write(path, history.join("\n") + "\n");
+            }
+        }
+        LineEditor {
+            history,
+            history_path,
+            raw,
+        }
+    }
+
+    pub fn read_line(&mut self, prompt: &str) -> Result<ReadLine, MyError> {
+        let mut stdout = io::stdout();
+        let result = match self.raw {
+            true => {
+                let _raw_mode = raw_mode::enable()?;
+                edit(&mut io::stdin().lock(), &mut stdout, prompt, &self.history)?
+            }
+            false => {
+                print!("{prompt}");
+                stdout.flush()?;
+                let mut buffer = String::new();
+                match io::stdin().lock().read_line(&mut buffer)? {
+                    0 => ReadLine::Eof,
+                    _ => ReadLine::Line(buffer.trim_end_matches(['\r', '\n']).to_string()),
+                }
+            }
+        };
+        if let ReadLine::Line(line) = &result {
+            self.add_history(line);
+        }
+        Ok(result)
+    }
+
+    fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if let Some(path) = &self.history_path {
+            // Losing the history is not worth interrupting the session, here
+            // or when trimming it in `new`
+            let _ = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{line}"));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+    KillToEnd,
+    KillToStart,
+    /// Ctrl-R
+    Search,
+    /// Ctrl-G
+    Cancel,
+    /// Ctrl-C
+    Interrupt,
+    /// Ctrl-D
+    Eof,
+    Other,
+}
+
+/// Reads one key press, `None` at the end of input.
+fn read_key(input: &mut dyn Read) -> Result<Option<Key>, MyError> {
+    let byte = match read_byte(input)? {
+        Some(byte) => byte,
+        None => return Ok(None),
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        1 => Key::Home,
+        2 => Key::Left,
+        3 => Key::Interrupt,
+        4 => Key::Eof,
+        5 => Key::End,
+        6 => Key::Right,
+        7 => Key::Cancel,
+        8 | 127 => Key::Backspace,
+        11 => Key::KillToEnd,
+        14 => Key::Down,
+        16 => Key::Up,
+        18 => Key::Search,
+        21 => Key::KillToStart,
+        0x1b => read_escape(input)?,
+        0..=0x1f => Key::Other,
+        _ => read_char(input, byte)?,
+    };
+    Ok(Some(key))
+}
+
+/// Decodes the sequence following an ESC, `ESC [ A` or `ESC O A` for the
+/// arrows and `ESC [ 3 ~` for the numbered keys. Sequences with parameters,
+/// like `ESC [ 1 ; 5 D`, are read up to their final byte and ignored.
+fn read_escape(input: &mut dyn Read) -> Result<Key, MyError> {
+    if !matches!(read_byte(input)?, Some(b'[' | b'O')) {
+        return Ok(Key::Other);
+    }
+    let key = match read_byte(input)? {
+        Some(b'A') => Key::Up,
+        Some(b'B') => Key::Down,
+        Some(b'C') => Key::Right,
+        Some(b'D') => Key::Left,
+        Some(b'H') => Key::Home,
+        Some(b'F') => Key::End,
+        Some(first @ b'0'..=b'9') => {
+            let mut number = vec![first];
+            let last = loop {
+                match read_byte(input)? {
+                    Some(byte @ 0x40..=0x7e) => break Some(byte),
+                    Some(byte) => number.push(byte),
+                    None => break None,
+                }
+            };
+            match (number.as_slice(), last) {
+                (b"1" | b"7", Some(b'~')) => Key::Home,
+                (b"4" | b"8", Some(b'~')) => Key::End,
+                (b"3", Some(b'~')) => Key::Delete,
+                _ => Key::Other,
+            }
+        }
+        _ => Key::Other,
+    };
+    Ok(key)
+}
+
+/// Reads the rest of the UTF-8 character starting with `first`.
+fn read_char(input: &mut dyn Read, first: u8) -> Result<Key, MyError> {
+    let len = match first.leading_ones() {
+        0 => 1,
+        n @ 2..=4 => n as usize,
+        _ => return Ok(Key::Other),
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match read_byte(input)? {
+            Some(byte) => bytes.push(byte),
+            None => return Ok(Key::Other),
+        }
+    }
+    Ok(match std::str::from_utf8(&bytes) {
+        Ok(s) => Key::Char(s.chars().next().unwrap()),
+        Err(_) => Key::Other,
+    })
+}
+
+/// The line being edited.
+#[derive(Default)]
+struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Line {
+    fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    fn render(&self, output: &mut dyn Write, prompt: &str) -> Result<(), MyError> {
+        write!(output, "\r{prompt}{}\x1b[K", self.text())?;
+        let back = self.chars.len() - self.cursor;
+        if back > 0 {
+            write!(output, "\x1b[{back}D")?;
+        }
+        output.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads a line from a terminal in raw mode, echoing and editing it on
+/// `output`.
+fn edit(
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+    prompt: &str,
+    history: &[String],
+) -> Result<ReadLine, MyError> {
+    let mut line = Line::default();
+    // Index in `history` of the entry shown, `history.len()` for the new line
+    let mut entry = history.len();
+    let mut draft = String::new();
+    // Key that ended a search, handled as if just pressed
+    let mut pending = None;
+    line.render(output, prompt)?;
+    loop {
+        let key = match pending.take() {
+            Some(key) => key,
+            None => match read_key(input)? {
+                Some(key) => key,
+                None => return Ok(ReadLine::Eof),
+            },
+        };
+        match key {
+            Key::Enter => {
+                write!(output, "\r\n")?;
+                return Ok(ReadLine::Line(line.text()));
+            }
+            Key::Interrupt => {
+                write!(output, "^C\r\n")?;
+                return Ok(ReadLine::Interrupted);
+            }
+            Key::Eof if line.chars.is_empty() => return Ok(ReadLine::Eof),
+            Key::Eof | Key::Delete if line.cursor < line.chars.len() => {
+                line.chars.remove(line.cursor);
+            }
+            Key::Char(c) => {
+                line.chars.insert(line.cursor, c);
+                line.cursor += 1;
+            }
+            Key::Backspace if line.cursor > 0 => {
+                line.cursor -= 1;
+                line.chars.remove(line.cursor);
+            }
+            Key::Left => line.cursor = line.cursor.saturating_sub(1),
+            Key::Right => line.cursor = (line.cursor + 1).min(line.chars.len()),
+            Key::Home => line.cursor = 0,
+            Key::End => line.cursor = line.chars.len(),
+            Key::KillToEnd => line.chars.truncate(line.cursor),
+            Key::KillToStart => {
+                line.chars.drain(..line.cursor);
+                line.cursor = 0;
+            }
+            Key::Up if entry > 0 => {
+                if entry == history.len() {
+                    draft = line.text();
+                }
+                entry -= 1;
+                line.set(&history[entry]);
+            }
+            Key::Down if entry < history.len() => {
+                entry += 1;
+                line.set(history.get(entry).unwrap_or(&draft));
+            }
+            Key::Search => match search(input, output, history)? {
+                Some((found, key)) => {
+                    if let Some(found) = found {
+                        line.set(&found);
+                    }
+                    pending = key;
+                }
+                None => return Ok(ReadLine::Eof),
+            },
+            _ => (),
+        }
+        line.render(output, prompt)?;
+    }
+}
+
+/// Incremental search backwards through `history`. Returns the entry found,
+/// if any was accepted, and the key that ended the search unless it was a
+/// cancel. `None` at the end of input.
+#[allow(clippy::type_complexity)]
+fn search(
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+    history: &[String],
+) -> Result<Option<(Option<String>, Option<Key>)>, MyError> {
+    let mut query = String::new();
+    // Index in `history` of the current match
+    let mut found: Option<usize> = None;
+    let find_before =
+        |query: &str, end: usize| history[..end].iter().rposition(|e| e.contains(query));
+    loop {
+        let shown = found.map_or("", |i| history[i].as_str());
+        write!(output, "\r(reverse-i-search)`{query}': {shown}\x1b[K")?;
+        output.flush()?;
+
+        let key = match read_key(input)? {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        match key {
+            Key::Char(c) => {
+                query.push(c);
+                // The current match may still contain the longer query
+                found = find_before(&query, found.map_or(history.len(), |i| i + 1));
+            }
+            Key::Backspace => {
+                query.pop();
+                found = find_before(&query, history.len());
+            }
+            Key::Search => {
+                if let Some(older) = find_before(&query, found.unwrap_or(history.len())) {
+                    found = Some(older);
+                }
+            }
+            Key::Cancel | Key::Interrupt => return Ok(Some((None, None))),
+            key => return Ok(Some((found.map(|i| history[i].clone()), Some(key)))),
+        }
+    }
+}
+
+/// Switching the terminal in and out of raw mode with termios.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod raw_mode {
+    use crate::errors::MyError;
+    use std::io;
+
+    pub const SUPPORTED: bool = true;
+
+    // Values of the glibc and musl headers for these targets
+    const NCCS: usize = 32;
+    const ICRNL: u32 = 0o400;
+    const IXON: u32 = 0o2000;
+    const ISIG: u32 = 0o1;
+    const ICANON: u32 = 0o2;
+    const ECHO: u32 = 0o10;
+    const IEXTEN: u32 = 0o100000;
+    const VTIME: usize = 5;
+    const VMIN: usize = 6;
+    const TCSADRAIN: i32 = 1;
+    const STDIN_FILENO: i32 = 0;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Termios {
+        c_iflag: u32,
+        c_oflag: u32,
+        c_cflag: u32,
+        c_lflag: u32,
+        c_line: u8,
+        c_cc: [u8; NCCS],
+        c_ispeed: u32,
+        c_ospeed: u32,
+    }
+
+    extern "C" {
+        fn tcgetattr(fd: i32, termios: *mut Termios) -> i32;
+        fn tcsetattr(fd: i32, optional_actions: i32, termios: *const Termios) -> i32;
+    }
+
+    /// Restores the terminal settings when dropped.
+    pub struct RawMode {
+        original: Termios,
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            unsafe {
+                tcsetattr(STDIN_FILENO, TCSADRAIN, &self.original);
+            }
+        }
+    }
+
+    /// Reads stdin one key at a time, without echo or signals, so that
+    /// Ctrl-C only discards the line.
+    pub fn enable() -> Result<RawMode, MyError> {
+        let mut termios = Termios {
+            c_iflag: 0,
+            c_oflag: 0,
+            c_cflag: 0,
+            c_lflag: 0,
+            c_line: 0,
+            c_cc: [0; NCCS],
+            c_ispeed: 0,
+            c_ospeed: 0,
+        };
+        if unsafe { tcgetattr(STDIN_FILENO, &mut termios) } != 0 {
+            return Err(MyError::Io(io::Error::last_os_error()));
+        }
+        let original = termios;
+        termios.c_iflag &= !(ICRNL | IXON);
+        termios.c_lflag &= !(ECHO | ICANON | ISIG | IEXTEN);
+        termios.c_cc[VMIN] = 1;
+        termios.c_cc[VTIME] = 0;
+        if unsafe { tcsetattr(STDIN_FILENO, TCSADRAIN, &termios) } != 0 {
+            return Err(MyError::Io(io::Error::last_os_error()));
+        }
+        Ok(RawMode { original })
+    }
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+mod raw_mode {
+    use crate::errors::MyError;
+
+    pub const SUPPORTED: bool = false;
+
+    pub struct RawMode;
+
+    pub fn enable() -> Result<RawMode, MyError> {
+        unreachable!("raw mode is not supported on this target")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit, ReadLine};
+
+    fn type_keys(keys: &[u8], history: &[&str]) -> String {
+        let history: Vec<String> = history.iter().map(|s| s.to_string()).collect();
+        let mut output = Vec::new();
+        match edit(&mut &keys[..], &mut output, "> ", &history).unwrap() {
+            ReadLine::Line(line) => line,
+            ReadLine::Interrupted => "<interrupted>".to_string(),
+            ReadLine::Eof => "<eof>".to_string(),
+        }
+    }
+
+    #[test]
+    fn edits_at_the_cursor() {
+        assert_eq!(type_keys(b"+-\x1b[D.\r", &[]), "+.-");
+        assert_eq!(type_keys(b"abc\x01x\x05y\r", &[]), "xabcy");
+        assert_eq!(type_keys(b"abc\x1b[D\x1b[D\x7f\x1b[3~\r", &[]), "c");
+        assert_eq!(type_keys(b"abcd\x1b[D\x1b[D\x0b\r", &[]), "ab");
+        assert_eq!(type_keys(b"ab\x1b[1;5Dc\r", &[]), "abc");
+        assert_eq!(type_keys("é+\r".as_bytes(), &[]), "é+");
+    }
+
+    #[test]
+    fn walks_the_history() {
+        let history = ["+++", ":tape 0..4"];
+        assert_eq!(type_keys(b"\x1b[A\r", &history), ":tape 0..4");
+        assert_eq!(type_keys(b"\x1b[A\x1b[A\x1b[A.\r", &history), "+++.");
+        assert_eq!(type_keys(b"-\x1b[A\x1b[B\r", &history), "-");
+    }
+
+    #[test]
+    fn searches_the_history() {
+        let history = ["+++", ":tape 0..4", "+[>+<-]", ":ptr 2"];
+        assert_eq!(type_keys(b"\x12+\r", &history), "+[>+<-]");
+        assert_eq!(type_keys(b"\x12+\x12\r", &history), "+++");
+        assert_eq!(type_keys(b"\x12ta\x1b[D-\r", &history), ":tape 0..-4");
+        assert_eq!(type_keys(b"-\x12ta\x07\r", &history), "-");
+    }
+
+    #[test]
+    fn stops_on_interrupt_and_eof() {
+        assert_eq!(type_keys(b"+\x03", &[]), "<interrupted>");
+        assert_eq!(type_keys(b"\x04", &[]), "<eof>");
+        assert_eq!(type_keys(b"+", &[]), "<eof>");
+    }
+}
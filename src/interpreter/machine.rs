@@ -0,0 +1,154 @@
+use super::embed::check_embedded;
+use super::{exec_memory_token, memory, ExecQueue};
+use crate::errors::MyError;
+use crate::parse_args::Config;
+use crate::{compile_config, CellType, Eof, Memory, Outcome, Token};
+
+/// What a `Machine` stopped for.
+#[derive(Debug)]
+pub enum Event {
+    /// `.` output the value of a cell
+    Output(u32),
+    /// `,` is waiting for `Machine::provide_input` or `Machine::close_input`
+    NeedInput,
+    /// The program finished
+    Halted,
+    /// The program failed, the machine is halted
+    Error(MyError),
+}
+
+/// Runs a program one token at a time, leaving IO to the host.
+///
+/// Nothing blocks: output and input requests come back as `Event`s, so a GUI
+/// or an async service can drive the program at its own pace.
+///
+/// ```
+/// use brainfuck_hcy::{Event, Machine};
+///
+/// let mut machine = Machine::new(",+.").unwrap();
+/// assert!(matches!(machine.run_until_event(), Event::NeedInput));
+/// machine.provide_input(64).unwrap();
+/// assert!(matches!(machine.run_until_event(), Event::Output(65)));
+/// assert!(matches!(machine.run_until_event(), Event::Halted));
+/// ```
+pub struct Machine {
+    exec_queue: ExecQueue,
+    memory: Memory<u32>,
+    eof: Eof,
+    /// Offset of the `,` waiting for input
+    pending_input: Option<i32>,
+}
+
+impl Machine {
+    /// Compiles `code` with the defaults of the command line.
+    pub fn new(code: &str) -> Result<Self, MyError> {
+        let mut config = Config::new();
+        config.raw_code = code.to_string();
+        Machine::with_config(config)
+    }
+
+    /// Compiles `config.raw_code` with the cell, tape, overflow, EOF and
+    /// memory limit settings of `config`. Only u8, u16 and u32 cells are
+    /// supported. Step and time limits are rejected, the host decides how
+    /// long to step.
+    pub fn with_config(mut config: Config) -> Result<Self, MyError> {
+        check_embedded(&config, "Machine")?;
+        if config.jit || config.cell_type != CellType::U32 {
+            return Err(MyError::Custom(
+                "Machine only supports u8, u16 and u32 cells without the JIT".to_string(),
+            ));
+        }
+        if config.max_steps.is_some() || config.timeout.is_some() {
+            return Err(MyError::Custom(
+                "Machine leaves step and time limits to the host".to_string(),
+            ));
+        }
+        compile_config(&mut config)?;
+        Ok(Machine {
+            memory: memory(&config, config.cell_max),
+            eof: config.eof,
+            exec_queue: ExecQueue::new(config.token_vec, config.spans),
+            pending_input: None,
+        })
+    }
+
+    /// Executes the next token, `None` if it didn't produce an event.
+    /// Repeats `NeedInput` until input is provided and `Halted` once the
+    /// program is done.
+    pub fn step(&mut self) -> Option<Event> {
+        if self.pending_input.is_some() {
+            return Some(Event::NeedInput);
+        }
+        let token = match self.exec_queue.next_token() {
+            Some(token) => token,
+            None => return Some(Event::Halted),
+        };
+        let result = match token {
+            Token::Output { offset } => match self.memory.output_at(offset) {
+                Ok(&n) => return Some(Event::Output(n)),
+                Err(e) => Err(e),
+            },
+            Token::Input { offset } => {
+                // Fail now on a bad offset rather than once input arrives
+                match self.memory.cell_index(offset) {
+                    Ok(_) => {
+                        self.pending_input = Some(offset);
+                        return Some(Event::NeedInput);
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            _ => exec_memory_token(token, &mut self.exec_queue, &mut self.memory),
+        };
+        match result {
+            Ok(()) => None,
+            Err(e) => Some(self.fail(e)),
+        }
+    }
+
+    /// Steps until an event.
+    pub fn run_until_event(&mut self) -> Event {
+        loop {
+            if let Some(event) = self.step() {
+                return event;
+            }
+        }
+    }
+
+    /// Answers `NeedInput` with `n`, which must fit in a cell.
+    pub fn provide_input(&mut self, n: u32) -> Result<(), MyError> {
+        let offset = self.take_pending_input()?;
+        self.memory.input_u32(offset, n).inspect_err(|_| {
+            self.pending_input = Some(offset);
+        })
+    }
+
+    /// Answers `NeedInput` with the end of input, storing what the `eof`
+    /// setting says.
+    pub fn close_input(&mut self) -> Result<(), MyError> {
+        let offset = self.take_pending_input()?;
+        self.memory.input_eof(offset, self.eof)
+    }
+
+    /// The tape and pointer as they are now.
+    pub fn state(&self) -> Outcome {
+        self.memory.outcome()
+    }
+
+    pub fn halted(&self) -> bool {
+        self.pending_input.is_none() && self.exec_queue.ptr >= self.exec_queue.view.len()
+    }
+
+    fn take_pending_input(&mut self) -> Result<i32, MyError> {
+        self.pending_input
+            .take()
+            .ok_or_else(|| MyError::Custom("The program is not waiting for input".to_string()))
+    }
+
+    /// Halts on `e`, located at the token that failed.
+    fn fail(&mut self, e: MyError) -> Event {
+        let e = e.at(self.exec_queue.spans[self.exec_queue.ptr - 1]);
+        self.exec_queue.ptr = self.exec_queue.view.len();
+        Event::Error(e)
+    }
+}
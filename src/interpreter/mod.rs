@@ -1,25 +1,35 @@
+use crate::cell::{BigInt, Cell};
 use crate::errors::MyError;
 use crate::parse_args::Config;
-use crate::{raw_code_to_token_vec, Memory, OutputMode, Token, IO};
-use std::io::{self, Write};
+use crate::{CellType, Memory, Outcome, OutputEncoding, OutputMode, Span, Token, IO};
+use std::io::BufWriter;
+use std::time::{Duration, Instant};
 
-static REPL_HELP: &str = "Commands:
-exit         Exit REPL mode
-clear        Clear memory
-v            Enable verbose mode
-uv           Disable verbose mode
-? | help     Print this";
+mod debugger;
+mod embed;
+#[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+mod jit;
+mod line_editor;
+mod machine;
+mod profiler;
+mod repl;
+mod session;
+
+pub use embed::Interpreter;
+pub use machine::{Event, Machine};
 
 #[derive(Debug)]
 struct ExecQueue {
     view: Vec<Token>,
+    spans: Vec<Span>,
     ptr: usize,
 }
 
 impl ExecQueue {
-    fn new(token_vec: Vec<Token>) -> Self {
+    fn new(token_vec: Vec<Token>, spans: Vec<Span>) -> Self {
         ExecQueue {
             view: token_vec,
+            spans,
             ptr: 0,
         }
     }
@@ -42,160 +52,296 @@ impl ExecQueue {
     }
 }
 
-pub fn run(mut config: Config) -> Result<(), MyError> {
-    let mut runtime_memory = Memory::new(config.cell_max);
+/// Steps between two reads of the clock for `--timeout`.
+const STEPS_PER_CLOCK_CHECK: u64 = 4096;
+
+/// Step and time budget of a run, checked before each token.
+struct Limits {
+    steps: u64,
+    max_steps: Option<u64>,
+    timeout: Option<Duration>,
+    start: Instant,
+    /// Step count of the next check, so that most steps cost one comparison.
+    next_check: u64,
+}
+
+impl Limits {
+    /// `None` when there is nothing to check.
+    fn new(max_steps: Option<u64>, timeout: Option<Duration>) -> Option<Self> {
+        if max_steps.is_none() && timeout.is_none() {
+            return None;
+        }
+        let mut limits = Limits {
+            steps: 0,
+            max_steps,
+            timeout,
+            start: Instant::now(),
+            next_check: 0,
+        };
+        limits.next_check = limits.schedule();
+        Some(limits)
+    }
+
+    #[inline]
+    fn step(&mut self) -> Result<(), MyError> {
+        self.steps += 1;
+        if self.steps > self.next_check {
+            return self.check();
+        }
+        Ok(())
+    }
+
+    #[cold]
+    fn check(&mut self) -> Result<(), MyError> {
+        if let Some(max_steps) = self.max_steps {
+            if self.steps > max_steps {
+                return Err(MyError::StepLimit(max_steps));
+            }
+        }
+        if let Some(timeout) = self.timeout {
+            if self.start.elapsed() >= timeout {
+                return Err(MyError::Timeout(timeout));
+            }
+        }
+        self.next_check = self.schedule();
+        Ok(())
+    }
+
+    fn schedule(&self) -> u64 {
+        let clock = match self.timeout {
+            Some(_) => self.steps + STEPS_PER_CLOCK_CHECK,
+            None => u64::MAX,
+        };
+        self.max_steps
+            .map_or(clock, |max_steps| max_steps.min(clock))
+    }
+}
+
+/// Budget checked before each token by `normal_mode`, which is compiled
+/// once for `Limits` and once for `Unlimited`, so that runs without limits
+/// don't pay for a check on every token.
+trait Budget {
+    fn step(&mut self) -> Result<(), MyError>;
+}
+
+impl Budget for Limits {
+    #[inline]
+    fn step(&mut self) -> Result<(), MyError> {
+        Limits::step(self)
+    }
+}
+
+struct Unlimited;
+
+impl Budget for Unlimited {
+    #[inline(always)]
+    fn step(&mut self) -> Result<(), MyError> {
+        Ok(())
+    }
+}
+
+/// The IO described by `config`, on stdin and stdout.
+pub fn io(config: &Config) -> IO<'static> {
     let with_buffer = config.output_mode == OutputMode::Bulk || config.verbose;
-    let mut io = IO::new(config.output_mode, with_buffer);
+    let io = IO::new(
+        config.output_mode,
+        with_buffer,
+        config.eof,
+        config.input_mode,
+        config.output_encoding,
+    );
+    // Bytes aren't flushed after each write, so give them a real buffer
+    // rather than the line buffering of stdout. The REPL and the debugger
+    // print between tokens and keep writing straight to stdout.
+    match config.output_encoding == OutputEncoding::Bytes && !config.repl_mode && !config.debug {
+        true => IO {
+            output: Box::new(BufWriter::new(std::io::stdout())),
+            ..io
+        },
+        false => io,
+    }
+}
 
-    if config.repl_mode {
-        println!("{}", REPL_HELP);
-        println!();
-        println!("{}", runtime_memory);
-        println!();
-        loop {
-            match repl_mode(&mut runtime_memory, &mut io, &mut config.verbose) {
-                Ok(_) => break,
-                Err(e) => {
-                    println!("Recovering from error: {e}");
-                    println!("{}", runtime_memory);
-                    println!("{}", io.buffer_to_string());
-                }
-            };
+/// Runs the compiled `config.token_vec` in the mode selected by `config`.
+pub fn run(config: Config, io: IO) -> Result<Outcome, MyError> {
+    config.check_backend()?;
+    if config.jit {
+        // `check_backend` only lets the JIT run u32 cells on the default tape
+        #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+        return jit::run(
+            memory(&config, config.cell_max),
+            io,
+            &config.token_vec,
+            &config.spans,
+        )
+        .map(Memory::into_outcome);
+        #[cfg(not(all(feature = "jit", target_arch = "x86_64", target_os = "linux")))]
+        return Err(MyError::Custom(
+            "This build does not include the JIT, rebuild with `--features jit` on Linux x86-64"
+                .to_string(),
+        ));
+    }
+    // `cell_max` only applies to the u32 cells, the others use their full range
+    match config.cell_type {
+        CellType::U32 => {
+            let cell_max = config.cell_max;
+            run_with(memory(&config, cell_max), io, config)
         }
-    } else {
-        normal_mode(
+        CellType::U64 => run_with(memory(&config, u64::MAX), io, config),
+        CellType::Big => run_with(memory(&config, BigInt::default()), io, config),
+    }
+}
+
+fn memory<C: Cell>(config: &Config, cell_max: C) -> Memory<C> {
+    Memory::new(
+        cell_max,
+        config.tape,
+        config.overflow,
+        config.signed,
+        config.max_memory,
+    )
+}
+
+fn run_with<C: Cell>(
+    mut runtime_memory: Memory<C>,
+    io: IO,
+    config: Config,
+) -> Result<Outcome, MyError> {
+    if config.repl_mode {
+        runtime_memory = repl::run(runtime_memory, io, &config)?;
+    } else if config.debug {
+        runtime_memory = debugger::run(runtime_memory, io, &config.raw_code)?;
+    } else if config.profile {
+        runtime_memory = profiler::run(
             runtime_memory,
             io,
-            config.verbose,
-            ExecQueue::new(config.token_vec),
+            ExecQueue::new(config.token_vec, config.spans),
+            Limits::new(config.max_steps, config.timeout),
         )?;
+    } else {
+        let exec_queue = ExecQueue::new(config.token_vec, config.spans);
+        runtime_memory = match Limits::new(config.max_steps, config.timeout) {
+            Some(limits) => normal_mode(runtime_memory, io, config.verbose, exec_queue, limits)?,
+            None => normal_mode(runtime_memory, io, config.verbose, exec_queue, Unlimited)?,
+        };
     }
-    Ok(())
+    Ok(runtime_memory.into_outcome())
 }
 
-fn normal_mode(
-    mut runtime_memory: Memory,
+// Never inlined: inlined into its callers the loop loses registers and
+// runs a fourth slower.
+#[inline(never)]
+fn normal_mode<C: Cell, B: Budget>(
+    mut runtime_memory: Memory<C>,
     mut io: IO,
     verbose: bool,
     mut exec_queue: ExecQueue,
-) -> Result<(), MyError> {
+    mut budget: B,
+) -> Result<Memory<C>, MyError> {
     let should_print_individually = !verbose && io.output_mode == OutputMode::Individually;
     while let Some(token) = exec_queue.next_token() {
-        if verbose {
-            print!("{} ", runtime_memory);
-        }
-        match token {
-            Token::PtrIncrease(n) => runtime_memory.ptr_increase(n),
-            Token::PtrDecrease(n) => runtime_memory.ptr_decrease(n)?,
-            Token::DataIncrease(n) => runtime_memory.data_increase(n),
-            Token::DataDecrease(n) => runtime_memory.data_decrease(n),
-            Token::JumpForward(n) => {
-                if runtime_memory.output() == 0 {
-                    exec_queue.jump_forward(n);
-                }
-            }
-            Token::JumpBack(n) => {
-                if runtime_memory.output() != 0 {
-                    exec_queue.jump_back(n);
-                }
-            }
-            Token::Output => {
-                let char = io.output(&runtime_memory)?;
-                if should_print_individually {
-                    print!("{}", char);
-                    if let Err(e) = io::stdout().flush() {
-                        return Err(MyError::Io(e));
-                    }
-                }
-            }
-            Token::Input => io.input(&mut runtime_memory)?,
+        budget.step().map_err(|e| io.failed(e))?;
+        let result = match verbose {
+            true => exec_verbose(token, &mut exec_queue, &mut runtime_memory, &mut io),
+            false => exec_token(
+                token,
+                &mut exec_queue,
+                &mut runtime_memory,
+                &mut io,
+                should_print_individually,
+            ),
         };
-        if verbose {
-            if token != Token::Input {
-                println!("{:?}", token);
-            }
-            println!("{}", io.buffer_to_string());
-        }
+        result.map_err(|e| io.failed(e))?;
     }
     if !verbose && io.output_mode == OutputMode::Bulk {
-        print!("{}", io.buffer_to_string());
+        io.write_buffer()?;
     };
-    Ok(())
+    io.flush()?;
+    Ok(runtime_memory)
 }
 
-fn repl_mode(runtime_memory: &mut Memory, io: &mut IO, verbose: &mut bool) -> Result<(), MyError> {
-    io.output_buffer = Some(Vec::new());
-    loop {
-        print!("> ");
-        if let Err(e) = io::stdout().flush() {
-            return Err(MyError::Io(e));
-        }
-
-        let mut buffer = String::new();
-        if let Err(e) = io::stdin().read_line(&mut buffer) {
-            return Err(MyError::Io(e));
-        }
-        let buffer = buffer.trim_end();
+/// `exec_token` between printing the memory and printing the token and the
+/// output so far. Kept out of line so that the printing doesn't slow down
+/// the loop of `normal_mode` when it is off.
+#[cold]
+#[inline(never)]
+fn exec_verbose<C: Cell>(
+    token: Token,
+    exec_queue: &mut ExecQueue,
+    runtime_memory: &mut Memory<C>,
+    io: &mut IO,
+) -> Result<(), MyError> {
+    print!("{} ", runtime_memory);
+    exec_token(token, exec_queue, runtime_memory, io, false)?;
+    if !matches!(token, Token::Input { .. }) {
+        println!("{:?}", token);
+    }
+    println!("{}", io.buffer_to_string());
+    Ok(())
+}
 
-        //EOF
-        if buffer.as_bytes().is_empty() {
-            println!()
-        }
+/// Executes a token just taken from `exec_queue`. Errors carry the span of
+/// that token.
+#[inline(always)]
+fn exec_token<C: Cell>(
+    token: Token,
+    exec_queue: &mut ExecQueue,
+    runtime_memory: &mut Memory<C>,
+    io: &mut IO,
+    should_print_individually: bool,
+) -> Result<(), MyError> {
+    let result = match token {
+        Token::Output { offset } => output(io, runtime_memory, offset, should_print_individually),
+        Token::Input { offset } => io.input(runtime_memory, offset),
+        _ => exec_memory_token(token, exec_queue, runtime_memory),
+    };
+    // Jumps never fail, so the token is still the one before `ptr`.
+    result.map_err(|e| e.at(exec_queue.spans[exec_queue.ptr - 1]))
+}
 
-        match buffer.trim() {
-            "exit" => break,
-            "clear" => {
-                runtime_memory.view = vec![0];
-                io.output_buffer = Some(Vec::new());
+/// Executes a token that doesn't do IO, `.` and `,` are left to the caller.
+/// Inlined so that the loop of `normal_mode` still dispatches with a single
+/// jump table.
+#[inline(always)]
+fn exec_memory_token<C: Cell>(
+    token: Token,
+    exec_queue: &mut ExecQueue,
+    runtime_memory: &mut Memory<C>,
+) -> Result<(), MyError> {
+    match token {
+        Token::PtrIncrease(n) => runtime_memory.ptr_increase(n),
+        Token::PtrDecrease(n) => runtime_memory.ptr_decrease(n),
+        Token::DataIncrease { offset, n } => runtime_memory.data_increase(offset, n),
+        Token::DataDecrease { offset, n } => runtime_memory.data_decrease(offset, n),
+        Token::JumpForward(n) => {
+            if runtime_memory.output().is_zero() {
+                exec_queue.jump_forward(n);
             }
-            "v" => *verbose = true,
-            "uv" => *verbose = false,
-            "?" | "help" => println!("{REPL_HELP}"),
-            _ => {}
+            Ok(())
         }
-
-        let mut exec_queue = ExecQueue::new(raw_code_to_token_vec(buffer)?);
-        let should_print_individually = !*verbose && io.output_mode == OutputMode::Individually;
-
-        while let Some(token) = exec_queue.next_token() {
-            if *verbose {
-                print!("{} ", runtime_memory);
-            }
-            match token {
-                Token::PtrIncrease(n) => runtime_memory.ptr_increase(n),
-                Token::PtrDecrease(n) => runtime_memory.ptr_decrease(n)?,
-                Token::DataIncrease(n) => runtime_memory.data_increase(n),
-                Token::DataDecrease(n) => runtime_memory.data_decrease(n),
-                Token::JumpForward(n) => {
-                    if runtime_memory.output() == 0 {
-                        exec_queue.jump_forward(n);
-                    }
-                }
-                Token::JumpBack(n) => {
-                    if runtime_memory.output() != 0 {
-                        exec_queue.jump_back(n);
-                    }
-                }
-                Token::Output => {
-                    let char = io.output(runtime_memory)?;
-                    if should_print_individually {
-                        print!("{}", char);
-                        if let Err(e) = io::stdout().flush() {
-                            return Err(MyError::Io(e));
-                        }
-                    }
-                }
-                Token::Input => io.input(runtime_memory)?,
-            };
-            if *verbose {
-                if token != Token::Input {
-                    println!("{:?}", token);
-                }
-                println!("{}", io.buffer_to_string());
+        Token::JumpBack(n) => {
+            if !runtime_memory.output().is_zero() {
+                exec_queue.jump_back(n);
             }
+            Ok(())
         }
-        println!("\r{}", runtime_memory);
-        println!("{}", io.buffer_to_string());
+        Token::SetZero { offset } => runtime_memory.set_zero(offset),
+        Token::ScanRight(n) => runtime_memory.scan_right(n),
+        Token::ScanLeft(n) => runtime_memory.scan_left(n),
+        Token::MulAdd { offset, factor } => runtime_memory.mul_add(offset, factor),
+        Token::Output { .. } | Token::Input { .. } => Ok(()),
+    }
+}
+
+fn output<C: Cell>(
+    io: &mut IO,
+    runtime_memory: &mut Memory<C>,
+    offset: i32,
+    should_print_individually: bool,
+) -> Result<(), MyError> {
+    let n = io.output(runtime_memory, offset)?;
+    if should_print_individually {
+        io.write(n)?;
     }
     Ok(())
 }
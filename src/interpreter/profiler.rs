@@ -0,0 +1,114 @@
+use super::{exec_token, ExecQueue, Limits};
+use crate::cell::Cell;
+use crate::errors::MyError;
+use crate::{Memory, OutputMode, Span, Token, IO};
+
+/// Number of loops listed in the report.
+const TOP_LOOPS: usize = 10;
+
+/// Runs like `normal_mode` while counting how often each token executes,
+/// then prints the loops with the most self steps to stderr. The report is
+/// printed even when the program or writing its output fails.
+pub fn run<C: Cell>(
+    mut runtime_memory: Memory<C>,
+    mut io: IO,
+    mut exec_queue: ExecQueue,
+    mut limits: Option<Limits>,
+) -> Result<Memory<C>, MyError> {
+    let should_print_individually = io.output_mode == OutputMode::Individually;
+    let mut counts = vec![0u64; exec_queue.view.len()];
+    let mut result = Ok(());
+    while let Some(token) = exec_queue.next_token() {
+        if let Some(limits) = &mut limits {
+            result = limits.step();
+            if result.is_err() {
+                break;
+            }
+        }
+        counts[exec_queue.ptr - 1] += 1;
+        result = exec_token(
+            token,
+            &mut exec_queue,
+            &mut runtime_memory,
+            &mut io,
+            should_print_individually,
+        );
+        if result.is_err() {
+            break;
+        }
+    }
+    let written = match io.output_mode {
+        OutputMode::Bulk => io.write_buffer(),
+        OutputMode::Individually => io.flush(),
+    };
+    report(&exec_queue.view, &exec_queue.spans, &counts);
+    result.and(written).map(|()| runtime_memory)
+}
+
+struct Loop {
+    span: Span,
+    entries: u64,
+    iterations: u64,
+    /// Steps spent in the loop, nested loops included.
+    steps: u64,
+    /// Steps spent in the loop's own tokens.
+    self_steps: u64,
+}
+
+fn report(token_vec: &[Token], spans: &[Span], counts: &[u64]) {
+    let total: u64 = counts.iter().sum();
+    let mut loops: Vec<Loop> = token_vec
+        .iter()
+        .enumerate()
+        .filter_map(|(i, token)| match *token {
+            // `end` is the index after the matching `JumpBack`
+            Token::JumpForward(end) => Some(Loop {
+                span: spans[i].to(spans[end as usize - 1]),
+                entries: counts[i],
+                iterations: counts[end as usize - 1],
+                steps: counts[i..end as usize].iter().sum(),
+                self_steps: self_steps(token_vec, counts, i, end as usize),
+            }),
+            _ => None,
+        })
+        .filter(|l| l.steps > 0)
+        .collect();
+    loops.sort_by_key(|l| std::cmp::Reverse(l.self_steps));
+
+    eprintln!();
+    eprintln!("Profile: {} steps, {} loops executed", total, loops.len());
+    if loops.is_empty() {
+        return;
+    }
+    eprintln!(
+        "{:<20} {:>12} {:>14} {:>16} {:>16} {:>7}",
+        "Loop", "Entries", "Iterations", "Steps", "Self steps", "Share"
+    );
+    for l in loops.iter().take(TOP_LOOPS) {
+        eprintln!(
+            "{:<20} {:>12} {:>14} {:>16} {:>16} {:>6.2}%",
+            l.span.to_string(),
+            l.entries,
+            l.iterations,
+            l.steps,
+            l.self_steps,
+            l.self_steps as f64 * 100.0 / total as f64
+        );
+    }
+}
+
+/// Sums the counts of the loop `start..end`, skipping nested loops.
+fn self_steps(token_vec: &[Token], counts: &[u64], start: usize, end: usize) -> u64 {
+    let mut sum = counts[start] + counts[end - 1];
+    let mut i = start + 1;
+    while i < end - 1 {
+        match token_vec[i] {
+            Token::JumpForward(nested_end) => i = nested_end as usize,
+            _ => {
+                sum += counts[i];
+                i += 1;
+            }
+        }
+    }
+    sum
+}
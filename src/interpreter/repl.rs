@@ -0,0 +1,415 @@
+use super::line_editor::{LineEditor, ReadLine};
+use super::session::Session;
+use super::{exec_token, ExecQueue, Limits};
+use crate::cell::Cell;
+use crate::errors::MyError;
+use crate::parse_args::Config;
+use crate::{compile, show_ir, Memory, OutputMode, Token, IO};
+use std::fs;
+use std::time::Duration;
+
+struct Command {
+    name: &'static str,
+    args: &'static str,
+    help: &'static str,
+}
+
+static COMMANDS: &[Command] = &[
+    Command {
+        name: "quit",
+        args: "",
+        help: "Exit the REPL",
+    },
+    Command {
+        name: "reset",
+        args: "",
+        help: "Clear the memory and the output",
+    },
+    Command {
+        name: "tape",
+        args: "[start..end]",
+        help: "Display the cells from start up to end, by default the whole tape",
+    },
+    Command {
+        name: "ptr",
+        args: "[position]",
+        help: "Display the position of the pointer, or move it",
+    },
+    Command {
+        name: "set",
+        args: "<position> <value>",
+        help: "Store a value in the cell at a position",
+    },
+    Command {
+        name: "load",
+        args: "<path>",
+        help: "Run the code of a file",
+    },
+    Command {
+        name: "save",
+        args: "<path>",
+        help: "Save the memory, output and code entered to a file",
+    },
+    Command {
+        name: "restore",
+        args: "<path>",
+        help: "Restore a session saved with :save",
+    },
+    Command {
+        name: "ir",
+        args: "",
+        help: "Toggle displaying the intermediate representation of each entry",
+    },
+    Command {
+        name: "cell",
+        args: "<u8|u16|u32>",
+        help: "Change the cell size, clearing the memory",
+    },
+    Command {
+        name: "mode",
+        args: "<individual|bulk>",
+        help: "Print each output right away, or only after each entry",
+    },
+    Command {
+        name: "verbose",
+        args: "",
+        help: "Toggle displaying the memory after each token",
+    },
+    Command {
+        name: "help",
+        args: "[command]",
+        help: "List the commands, or describe one",
+    },
+];
+
+fn usage(command: &Command) -> String {
+    format!(":{} {}", command.name, command.args)
+}
+
+fn unknown_command(name: &str) -> MyError {
+    MyError::Custom(format!(
+        "Unknown command \":{name}\", type :help for a list"
+    ))
+}
+
+fn print_help() {
+    println!("Commands:");
+    for command in COMMANDS {
+        println!("{:<26}{}", usage(command), command.help);
+    }
+    println!("Anything else is code, an unclosed '[' continues on the next line.");
+}
+
+/// Reads code and `:` commands from stdin until `:quit` or EOF. Errors are
+/// printed and the session goes on with the memory as the error left it.
+pub fn run<C: Cell>(
+    runtime_memory: Memory<C>,
+    io: IO,
+    config: &Config,
+) -> Result<Memory<C>, MyError> {
+    let mut repl = Repl {
+        runtime_memory,
+        io,
+        verbose: config.verbose,
+        show_ir: false,
+        optimize: config.optimize,
+        max_steps: config.max_steps,
+        timeout: config.timeout,
+        transcript: Vec::new(),
+    };
+    repl.io.output_buffer = Some(Vec::new());
+
+    print_help();
+    println!();
+    match &config.session {
+        Some(path) => repl.restore(path)?,
+        None => println!("{}", repl.runtime_memory),
+    }
+    println!();
+
+    let mut editor = LineEditor::new();
+    // Lines of a loop that isn't closed yet
+    let mut code = String::new();
+    loop {
+        let buffer = match editor.read_line(if code.is_empty() { "> " } else { ".. " })? {
+            ReadLine::Line(line) => line,
+            ReadLine::Interrupted => {
+                code.clear();
+                continue;
+            }
+            ReadLine::Eof => {
+                println!();
+                break;
+            }
+        };
+        let buffer = buffer.trim_end();
+
+        match buffer.trim_start().strip_prefix(':') {
+            Some(command) if code.is_empty() => match repl.command(command) {
+                Ok(Flow::Quit) => break,
+                Ok(Flow::Continue) => (),
+                Err(e) => println!("{e}"),
+            },
+            _ => {
+                code.push_str(buffer);
+                if has_unclosed_bracket(&code) {
+                    code.push('\n');
+                    continue;
+                }
+                repl.transcript.extend(code.lines().map(str::to_string));
+                repl.execute(&std::mem::take(&mut code));
+            }
+        }
+    }
+    Ok(repl.runtime_memory)
+}
+
+enum Flow {
+    Continue,
+    Quit,
+}
+
+struct Repl<'a, C: Cell> {
+    runtime_memory: Memory<C>,
+    io: IO<'a>,
+    verbose: bool,
+    show_ir: bool,
+    optimize: bool,
+    max_steps: Option<u64>,
+    timeout: Option<Duration>,
+    /// Lines of code entered so far, for `:save`.
+    transcript: Vec<String>,
+}
+
+impl<C: Cell> Repl<'_, C> {
+    /// Runs `code` on the current memory, reporting an error along with the
+    /// state it left behind.
+    fn execute(&mut self, code: &str) {
+        if let Err(e) = self.run_code(code) {
+            println!("Recovering from error: {e}");
+            println!("{}", self.runtime_memory);
+            println!("{}", self.io.buffer_to_string());
+        }
+    }
+
+    fn run_code(&mut self, code: &str) -> Result<(), MyError> {
+        let (token_vec, spans) = compile(
+            code,
+            self.optimize,
+            self.runtime_memory.arithmetic(),
+            self.runtime_memory.tape,
+        )?;
+        if self.show_ir {
+            show_ir(token_vec.clone());
+        }
+        let mut exec_queue = ExecQueue::new(token_vec, spans);
+        let should_print_individually =
+            !self.verbose && self.io.output_mode == OutputMode::Individually;
+        // Every entry gets the full budget
+        let mut limits = Limits::new(self.max_steps, self.timeout);
+
+        while let Some(token) = exec_queue.next_token() {
+            if let Some(limits) = &mut limits {
+                limits.step()?;
+            }
+            if self.verbose {
+                print!("{} ", self.runtime_memory);
+            }
+            exec_token(
+                token,
+                &mut exec_queue,
+                &mut self.runtime_memory,
+                &mut self.io,
+                should_print_individually,
+            )?;
+            if self.verbose {
+                if !matches!(token, Token::Input { .. }) {
+                    println!("{:?}", token);
+                }
+                println!("{}", self.io.buffer_to_string());
+            }
+        }
+        println!("\r{}", self.runtime_memory);
+        println!("{}", self.io.buffer_to_string());
+        Ok(())
+    }
+
+    /// Runs the command `line`, without its leading ':'.
+    fn command(&mut self, line: &str) -> Result<Flow, MyError> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+        let command = match COMMANDS.iter().find(|c| c.name == name) {
+            Some(command) => command,
+            None => return Err(unknown_command(name)),
+        };
+        let max_args = command.args.split_whitespace().count();
+        let min_args = command.args.matches('<').count();
+        if args.len() < min_args || args.len() > max_args {
+            return Err(MyError::Custom(format!("Usage: {}", usage(command))));
+        }
+
+        match (command.name, args.as_slice()) {
+            ("quit", _) => return Ok(Flow::Quit),
+            ("reset", _) => {
+                self.runtime_memory = self.runtime_memory.cleared();
+                self.io.output_buffer = Some(Vec::new());
+                self.transcript.clear();
+                println!("{}", self.runtime_memory);
+            }
+            ("tape", []) => println!("{}", self.runtime_memory),
+            ("tape", [range]) => {
+                let (start, end) = match range.split_once("..") {
+                    Some((start, end)) => (start.parse::<i64>()?, end.parse::<i64>()?),
+                    None => return Err(MyError::Custom(format!("Usage: {}", usage(command)))),
+                };
+                let cells: Vec<String> = (start..end)
+                    .map(|i| {
+                        let cell = self.runtime_memory.format_cell(&self.runtime_memory.get(i));
+                        match i == self.runtime_memory.position() {
+                            true => format!(">{}", cell),
+                            false => cell,
+                        }
+                    })
+                    .collect();
+                println!("{}: [{}]", start, cells.join(", "));
+            }
+            ("ptr", []) => println!("{}", self.runtime_memory.position()),
+            ("ptr", [position]) => {
+                self.runtime_memory.move_to(position.parse()?)?;
+                println!("{}", self.runtime_memory);
+            }
+            ("set", [position, value]) => {
+                self.runtime_memory.set(position.parse()?, value.parse()?)?;
+                println!("{}", self.runtime_memory);
+            }
+            ("load", [path]) => {
+                let code = fs::read_to_string(path)?;
+                self.transcript.extend(code.lines().map(str::to_string));
+                self.execute(&code);
+            }
+            ("save", [path]) => {
+                self.save(path)?;
+                println!("Saved to {path}");
+            }
+            ("restore", [path]) => self.restore(path)?,
+            ("ir", _) => {
+                self.show_ir = !self.show_ir;
+                println!("IR {}", if self.show_ir { "on" } else { "off" });
+            }
+            ("cell", [size]) => {
+                let cell_max = cell_max(size)
+                    .ok_or_else(|| MyError::Custom(format!("Invalid cell size type {size}")))?;
+                self.runtime_memory = self.runtime_memory.with_cell_max(cell_max)?;
+                println!("{}", self.runtime_memory);
+            }
+            ("mode", ["individual"]) => self.io.output_mode = OutputMode::Individually,
+            ("mode", ["bulk"]) => self.io.output_mode = OutputMode::Bulk,
+            ("mode", [mode]) => return Err(MyError::Custom(format!("Invalid output mode {mode}"))),
+            ("verbose", _) => {
+                self.verbose = !self.verbose;
+                println!("Verbose {}", if self.verbose { "on" } else { "off" });
+            }
+            ("help", []) => print_help(),
+            ("help", [name]) => match COMMANDS
+                .iter()
+                .find(|c| c.name == name.trim_start_matches(':'))
+            {
+                Some(command) => println!("{}\n    {}", usage(command), command.help),
+                None => return Err(unknown_command(name)),
+            },
+            _ => unreachable!("arguments are checked against `args`"),
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn save(&self, path: &str) -> Result<(), MyError> {
+        let memory = &self.runtime_memory;
+        Session {
+            cell: C::size_name(&memory.cell_max).to_string(),
+            ptr: memory.ptr as usize,
+            origin: memory.origin as usize,
+            tape: memory.view.iter().map(C::to_string).collect(),
+            output: self.io.output_buffer.clone().unwrap_or_default(),
+            code: self.transcript.clone(),
+        }
+        .write(path)
+    }
+
+    /// Replaces the memory, output and transcript with those saved in `path`,
+    /// keeping the tape and overflow settings.
+    fn restore(&mut self, path: &str) -> Result<(), MyError> {
+        let session = Session::read(path)?;
+        let memory = match (
+            C::size_name(&self.runtime_memory.cell_max),
+            cell_max(&session.cell),
+        ) {
+            (size, _) if size == session.cell => self.runtime_memory.cleared(),
+            (_, Some(cell_max)) if C::max_from_u32(cell_max).is_some() => {
+                self.runtime_memory.with_cell_max(cell_max)?
+            }
+            _ => {
+                return Err(MyError::Custom(format!(
+                    "{path} has {} cells, restart with --cell {}",
+                    session.cell, session.cell
+                )))
+            }
+        };
+        let view = session
+            .tape
+            .iter()
+            .map(|v| {
+                C::parse(v, &memory.cell_max).ok_or_else(|| {
+                    MyError::Custom(format!("{path}: {v} is not a {} cell value", session.cell))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        self.runtime_memory = memory.restored(view, session.ptr, session.origin)?;
+        self.io.output_buffer = Some(session.output);
+        self.transcript = session.code;
+
+        println!("{}", self.runtime_memory);
+        println!("{}", self.io.buffer_to_string());
+        Ok(())
+    }
+}
+
+/// Largest value of the cell sizes `:cell` switches between.
+fn cell_max(size: &str) -> Option<u32> {
+    match size {
+        "u8" => Some(255),
+        "u16" => Some(65535),
+        "u32" => Some(u32::MAX),
+        _ => None,
+    }
+}
+
+/// Whether a `[` in `code` is still waiting for its `]`. A `]` without a `[`
+/// is left for `compile` to report.
+fn has_unclosed_bracket(code: &str) -> bool {
+    let mut depth = 0;
+    for char in code.chars() {
+        match char {
+            '[' => depth += 1,
+            ']' if depth == 0 => return false,
+            ']' => depth -= 1,
+            _ => (),
+        }
+    }
+    depth > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::has_unclosed_bracket;
+
+    #[test]
+    fn detects_unclosed_brackets() {
+        assert!(has_unclosed_bracket("["));
+        assert!(has_unclosed_bracket("+[>[-]\n<"));
+        assert!(!has_unclosed_bracket("+[>[-]<]"));
+        assert!(!has_unclosed_bracket("+"));
+        // Left for `compile` to report
+        assert!(!has_unclosed_bracket("]["));
+    }
+}
@@ -0,0 +1,148 @@
+//! REPL sessions saved with `:save` and loaded with `:restore` or `--session`.
+//!
+//! A session file is text, one `key value` per line in this order:
+//!
+//! ```text
+//! brainfuck-hcy session 1
+//! cell u8
+//! ptr 2
+//! origin 0
+//! tape 0 0 65
+//! output 72 105
+//! code +++
+//! code [>+<-]
+//! ```
+//!
+//! `cell` is the cell size as given to `--cell`. `tape` lists every cell
+//! value, `ptr` is the index of the current cell in that list and `origin`
+//! the index of position 0, which only moves on an infinite tape. `output` is
+//! the output buffer as code points and every `code` line is a line of code
+//! entered so far, commands excluded. `output` and `code` may be empty.
+
+use crate::errors::MyError;
+use std::fs;
+
+const HEADER: &str = "brainfuck-hcy session 1";
+
+pub struct Session {
+    pub cell: String,
+    pub ptr: usize,
+    pub origin: usize,
+    pub tape: Vec<String>,
+    pub output: Vec<u32>,
+    pub code: Vec<String>,
+}
+
+impl Session {
+    pub fn read(path: &str) -> Result<Session, MyError> {
+        let text = fs::read_to_string(path)?;
+        let end = text.lines().count();
+        let mut lines = text.lines().enumerate();
+        let error = |line: usize, message: &str| {
+            MyError::Custom(format!("{}:{}: {}", path, line + 1, message))
+        };
+        match lines.next() {
+            Some((_, HEADER)) => (),
+            _ => return Err(error(0, &format!("Expected \"{HEADER}\""))),
+        }
+
+        let mut next = |key: &str| -> Result<String, MyError> {
+            match lines.next() {
+                Some((i, line)) => match line.split_once(' ').unwrap_or((line, "")) {
+                    (k, value) if k == key => Ok(value.to_string()),
+                    _ => Err(error(i, &format!("Expected \"{key}\""))),
+                },
+                None => Err(error(end, &format!("Expected \"{key}\""))),
+            }
+        };
+        let cell = next("cell")?;
+        let ptr = next("ptr")?.parse()?;
+        let origin = next("origin")?.parse()?;
+        let tape = next("tape")?
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+        let output = next("output")?
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+
+        let mut code = Vec::new();
+        for (i, line) in lines {
+            match line.strip_prefix("code ") {
+                Some(line) => code.push(line.to_string()),
+                None if line == "code" => code.push(String::new()),
+                None => return Err(error(i, "Expected \"code\"")),
+            }
+        }
+        Ok(Session {
+            cell,
+            ptr,
+            origin,
+            tape,
+            output,
+            code,
+        })
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), MyError> {
+        let output: Vec<String> = self.output.iter().map(u32::to_string).collect();
+        let mut text = format!(
+            "{HEADER}\ncell {}\nptr {}\norigin {}\ntape {}\noutput {}\n",
+            self.cell,
+            self.ptr,
+            self.origin,
+            self.tape.join(" "),
+            output.join(" ")
+        );
+        for line in &self.code {
+            text.push_str("code ");
+            text.push_str(line);
+            text.push('\n');
+        }
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Session;
+    use std::{env, fs, process};
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let path = env::temp_dir().join(format!("brainfuck-session-{}", process::id()));
+        let path = path.to_str().unwrap();
+        let session = Session {
+            cell: "big".to_string(),
+            ptr: 2,
+            origin: 1,
+            tape: vec!["-1".to_string(), "0".to_string(), "8589934592".to_string()],
+            output: vec![72, 105],
+            code: vec!["+[>+<-".to_string(), String::new(), "]".to_string()],
+        };
+        session.write(path).unwrap();
+        let restored = Session::read(path);
+        fs::remove_file(path).unwrap();
+
+        let restored = restored.unwrap();
+        assert_eq!(restored.cell, session.cell);
+        assert_eq!((restored.ptr, restored.origin), (2, 1));
+        assert_eq!(restored.tape, session.tape);
+        assert_eq!(restored.output, session.output);
+        assert_eq!(restored.code, session.code);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let path = env::temp_dir().join(format!("brainfuck-not-session-{}", process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "brainfuck-hcy session 1\ncell u8\norigin 0\n").unwrap();
+        let result = Session::read(path);
+        fs::remove_file(path).unwrap();
+
+        let message = result.err().unwrap().to_string();
+        assert!(message.ends_with(":3: Expected \"ptr\""), "{message}");
+    }
+}
@@ -1,18 +1,39 @@
+mod cell;
+mod codegen;
 mod errors;
 mod interpreter;
+mod optimizer;
 mod parse_args;
 
+use cell::{Cell, Overflowed};
+use optimizer::Arithmetic;
 use std::fmt;
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
 
+pub use cell::BigInt;
+pub use codegen::EmitTarget;
 pub use errors::MyError;
+pub use interpreter::{Event, Interpreter, Machine};
 pub use parse_args::Config;
 
 pub fn run(mut config: Config) -> Result<(), MyError> {
-    config.token_vec = raw_code_to_token_vec(&config.raw_code)?;
+    compile_config(&mut config)?;
     match config.show_ir {
         true => show_ir(config.token_vec),
-        false => interpreter::run(config)?,
+        false => match config.emit {
+            Some(target) => codegen::emit(
+                target,
+                &config.token_vec,
+                config.cell_max,
+                config.tape,
+                config.tape_len,
+                config.output_path.as_deref(),
+            )?,
+            None => {
+                let io = interpreter::io(&config);
+                interpreter::run(config, io)?;
+            }
+        },
     }
     Ok(())
 }
@@ -21,97 +42,339 @@ pub fn run(mut config: Config) -> Result<(), MyError> {
 pub enum Token {
     PtrIncrease(u32),
     PtrDecrease(u32),
-    DataIncrease(u32),
-    DataDecrease(u32),
+    DataIncrease { offset: i32, n: u32 },
+    DataDecrease { offset: i32, n: u32 },
     JumpForward(u32),
     JumpBack(u32),
-    Output,
-    Input,
+    Output { offset: i32 },
+    Input { offset: i32 },
+    SetZero { offset: i32 },
+    ScanRight(u32),
+    ScanLeft(u32),
+    MulAdd { offset: i32, factor: i32 },
+}
+
+/// Position of a character in the source code, both counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pos {
+    pub line: u32,
+    pub col: u32,
+}
+
+/// Source characters a token was parsed from, `end` inclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: Pos,
+    pub end: Pos,
+}
+
+impl Span {
+    /// Span from the start of `self` to the end of `other`.
+    fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
 }
 
-#[derive(Debug, PartialEq)]
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.start == self.end {
+            true => write!(f, "{}", self.start),
+            false => write!(f, "{}-{}", self.start, self.end),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OutputMode {
     Individually,
     Bulk,
 }
 
-struct IO {
+/// What `,` stores when stdin is at EOF.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Eof {
+    Zero,
+    /// `cell_max`, i.e. -1
+    Max,
+    Unchanged,
+}
+
+/// How `,` reads stdin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputMode {
+    /// Prompt and read one line per `,`, see `input_to_u32`
+    Line,
+    /// Read one byte per `,` without a prompt, or one UTF-8 encoded
+    /// character when cells can hold every Unicode scalar value
+    Raw,
+}
+
+/// How `.` turns a cell into bytes on stdout.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputEncoding {
+    /// The cell is a Unicode scalar value, written as UTF-8
+    Utf8,
+    /// The cell is a single Latin-1 byte, values above 255 are errors
+    Latin1,
+    /// The low 8 bits of the cell, written as is
+    Bytes,
+}
+
+struct IO<'a> {
     output_mode: OutputMode,
     output_buffer: Option<Vec<u32>>,
+    eof: Eof,
+    input_mode: InputMode,
+    output_encoding: OutputEncoding,
+    input: Box<dyn Read + 'a>,
+    output: Box<dyn Write + 'a>,
+    /// Print "Input:" to stdout before reading a line
+    prompt: bool,
 }
 
-impl IO {
-    fn new(output_mode: OutputMode, with_buffer: bool) -> Self {
+impl<'a> IO<'a> {
+    fn new(
+        output_mode: OutputMode,
+        with_buffer: bool,
+        eof: Eof,
+        input_mode: InputMode,
+        output_encoding: OutputEncoding,
+    ) -> Self {
         IO {
             output_mode,
             output_buffer: if with_buffer { Some(Vec::new()) } else { None },
+            eof,
+            input_mode,
+            output_encoding,
+            input: Box::new(io::stdin()),
+            output: Box::new(io::stdout()),
+            prompt: true,
         }
     }
 
-    fn buffer_to_string(&self) -> String {
-        let mut result = String::new();
-        for &n in self.output_buffer.as_ref().unwrap().iter() {
-            result.push(char::from_u32(n).unwrap());
+    /// Reads from `input` and writes to `output` instead of stdin and
+    /// stdout, without prompting.
+    fn with_streams<'b>(self, input: Box<dyn Read + 'b>, output: Box<dyn Write + 'b>) -> IO<'b> {
+        IO {
+            output_mode: self.output_mode,
+            output_buffer: self.output_buffer,
+            eof: self.eof,
+            input_mode: self.input_mode,
+            output_encoding: self.output_encoding,
+            input,
+            output,
+            prompt: false,
         }
-        result
     }
 
-    fn output(&mut self, runtime_memory: &Memory) -> Result<char, MyError> {
-        let n = runtime_memory.output();
-        let char = match char::from_u32(n) {
-            Some(c) => c,
-            None => {
-                return Err(MyError::Custom(format!(
+    fn encode(&self, n: u32, buffer: &mut [u8; 4]) -> Result<usize, MyError> {
+        match self.output_encoding {
+            OutputEncoding::Utf8 => match char::from_u32(n) {
+                Some(c) => Ok(c.encode_utf8(buffer).len()),
+                None => Err(MyError::Custom(format!(
                     "Invalid Unicode scalar value: {}",
                     n
-                )))
+                ))),
+            },
+            OutputEncoding::Latin1 if n > 255 => Err(MyError::Custom(format!(
+                "Value {} cannot be encoded as Latin-1",
+                n
+            ))),
+            OutputEncoding::Latin1 | OutputEncoding::Bytes => {
+                buffer[0] = n as u8;
+                Ok(1)
             }
+        }
+    }
+
+    /// The buffered output, encoded.
+    fn buffer_to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        let mut buffer = [0; 4];
+        for &n in self.output_buffer.as_ref().unwrap().iter() {
+            // every buffered value was checked by `output`
+            if let Ok(len) = self.encode(n, &mut buffer) {
+                result.extend_from_slice(&buffer[..len]);
+            }
+        }
+        result
+    }
+
+    fn buffer_to_string(&self) -> String {
+        String::from_utf8_lossy(&self.buffer_to_bytes()).into_owned()
+    }
+
+    /// Writes the buffered output to the output stream.
+    fn write_buffer(&mut self) -> Result<(), MyError> {
+        let bytes = self.buffer_to_bytes();
+        self.output.write_all(&bytes)?;
+        self.output.flush()?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), MyError> {
+        self.output.flush()?;
+        Ok(())
+    }
+
+    /// Flushes what was written before `e` stopped the run, so that it isn't
+    /// lost in a buffer. `e` is what gets reported, even if flushing fails.
+    #[cold]
+    fn failed(&mut self, e: MyError) -> MyError {
+        let _ = self.output.flush();
+        e
+    }
+
+    /// Reads the cell to output and checks that it can be encoded.
+    fn output<C: Cell>(
+        &mut self,
+        runtime_memory: &mut Memory<C>,
+        offset: i32,
+    ) -> Result<u32, MyError> {
+        let cell = runtime_memory.output_at(offset)?;
+        let n = match self.output_encoding {
+            OutputEncoding::Bytes => cell.low_byte() as u32,
+            _ => match cell.to_u32() {
+                Some(n) => n,
+                None if self.output_encoding == OutputEncoding::Latin1 => {
+                    return Err(MyError::Custom(format!(
+                        "Value {} cannot be encoded as Latin-1",
+                        cell
+                    )))
+                }
+                None => {
+                    return Err(MyError::Custom(format!(
+                        "Invalid Unicode scalar value: {}",
+                        cell
+                    )))
+                }
+            },
         };
+        self.encode(n, &mut [0; 4])?;
         if let Some(output_buffer) = &mut self.output_buffer {
             output_buffer.push(n);
         }
-        Ok(char)
+        Ok(n)
     }
 
-    fn input(&self, runtime_memory: &mut Memory) -> Result<(), MyError> {
-        print!("Input:");
-
-        if let Err(e) = io::stdout().flush() {
-            return Err(MyError::Io(e));
+    /// Writes `n` to the output stream. Text is flushed right away, bytes
+    /// are left to the buffering of the stream.
+    fn write(&mut self, n: u32) -> Result<(), MyError> {
+        let mut buffer = [0; 4];
+        let len = self.encode(n, &mut buffer)?;
+        self.output.write_all(&buffer[..len])?;
+        if self.output_encoding != OutputEncoding::Bytes {
+            self.output.flush()?;
         }
-        let mut buffer = String::new();
-        if let Err(e) = io::stdin().read_line(&mut buffer) {
-            return Err(MyError::Io(e));
-        }
-        let (n, eof) = input_to_u32(buffer)?;
-        if eof {
-            println!();
-        }
-        if n > runtime_memory.cell_max {
-            return Err(MyError::Custom(format!(
-                "Input value {} exceeds the maximum cell value {}",
-                n, runtime_memory.cell_max
-            )));
-        }
-        runtime_memory.input(n);
         Ok(())
     }
+
+    fn input<C: Cell>(
+        &mut self,
+        runtime_memory: &mut Memory<C>,
+        offset: i32,
+    ) -> Result<(), MyError> {
+        // Show buffered output before waiting for input
+        self.output.flush()?;
+        let max = &runtime_memory.cell_max;
+        let n = match self.input_mode {
+            InputMode::Line => read_line_input(&mut self.input, self.prompt)?,
+            InputMode::Raw => read_raw_input(
+                &mut self.input,
+                C::from_u32(char::MAX as u32, max).is_some(),
+            )?,
+        };
+        match n {
+            Some(n) => runtime_memory.input_u32(offset, n),
+            None => runtime_memory.input_eof(offset, self.eof),
+        }
+    }
 }
 
-struct Memory {
-    view: Vec<u32>,
+/// Shape of the interpreter's tape.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tape {
+    /// Starts at cell 0 and grows to the right as needed
+    Grow,
+    /// A fixed number of cells, moving past either end is an error
+    Fixed(u32),
+    /// A fixed number of cells, moving past either end wraps around
+    Wrap(u32),
+    /// Grows in both directions, positions left of the start are allowed
+    Infinite,
+}
+
+/// Type of the interpreter's cells.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CellType {
+    /// u8, u16 or u32 cells, bounded by `cell_max`
+    U32,
+    U64,
+    /// Signed cells of arbitrary size
+    Big,
+}
+
+/// What `+`, `-` and multiplication loops do when a cell leaves `0..=cell_max`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    Wrap,
+    /// Clamp to 0 or `cell_max`
+    Saturate,
+    /// Fail with an error
+    Trap,
+}
+
+/// State of the tape, at the end of a run of `Interpreter` or from
+/// `Machine::state`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    /// Every cell the tape grew to, from the leftmost one
+    pub tape: Cells,
+    /// Index in `tape` of the cell under the pointer
+    pub ptr: usize,
+    /// Index in `tape` of the starting cell, only moves for `Tape::Infinite`
+    pub origin: usize,
+}
+
+/// Cells of the tape, by `CellType`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cells {
+    U32(Vec<u32>),
+    U64(Vec<u64>),
+    Big(Vec<BigInt>),
+}
+
+struct Memory<C: Cell> {
+    view: Vec<C>,
     ptr: u32,
-    cell_max: u32,
+    cell_max: C,
+    tape: Tape,
+    /// Index in `view` of position 0, only moves for `Tape::Infinite`
+    origin: u32,
+    overflow: Overflow,
+    /// Display cells as two's complement signed values
+    signed: bool,
+    /// Largest number of cells the tape may grow to
+    max_len: Option<usize>,
 }
 
-impl fmt::Display for Memory {
+impl<C: Cell> fmt::Display for Memory<C> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut s = String::new();
-        for (i, &cell) in self.view.iter().enumerate() {
+        for (i, cell) in self.view.iter().enumerate() {
             if i == self.ptr as usize {
                 s.push('>');
             }
-            s.push_str(&cell.to_string());
+            s.push_str(&self.format_cell(cell));
             if i != self.view.len() - 1 {
                 s.push_str(", ");
             }
@@ -120,100 +383,434 @@ impl fmt::Display for Memory {
     }
 }
 
-impl Memory {
-    fn new(cell_max: u32) -> Self {
+impl<C: Cell> Memory<C> {
+    fn new(
+        cell_max: C,
+        tape: Tape,
+        overflow: Overflow,
+        signed: bool,
+        max_len: Option<usize>,
+    ) -> Self {
         Memory {
-            view: vec![0],
+            view: match tape {
+                Tape::Fixed(len) | Tape::Wrap(len) => vec![C::default(); len as usize],
+                Tape::Grow | Tape::Infinite => vec![C::default()],
+            },
             ptr: 0,
             cell_max,
+            tape,
+            origin: 0,
+            overflow,
+            signed,
+            max_len,
+        }
+    }
+
+    fn into_outcome(self) -> Outcome {
+        Outcome {
+            ptr: self.ptr as usize,
+            origin: self.origin as usize,
+            tape: C::into_cells(self.view),
+        }
+    }
+
+    fn outcome(&self) -> Outcome {
+        Outcome {
+            ptr: self.ptr as usize,
+            origin: self.origin as usize,
+            tape: C::into_cells(self.view.clone()),
         }
     }
 
-    fn ptr_increase(&mut self, n: u32) {
-        self.ptr += n;
-        if self.view.len() <= self.ptr as usize {
-            self.view.resize(self.ptr as usize + 1, 0);
+    /// How cell arithmetic behaves, see `compile`.
+    fn arithmetic(&self) -> Arithmetic {
+        arithmetic(C::BOUNDED, self.overflow)
+    }
+
+    /// The same memory layout and settings, with every cell cleared.
+    fn cleared(&self) -> Self {
+        Memory::new(
+            self.cell_max.clone(),
+            self.tape,
+            self.overflow,
+            self.signed,
+            self.max_len,
+        )
+    }
+
+    /// The same settings with cells of at most `cell_max`, every cell cleared.
+    fn with_cell_max(&self, cell_max: u32) -> Result<Self, MyError> {
+        match C::max_from_u32(cell_max) {
+            Some(cell_max) => Ok(Memory {
+                cell_max,
+                ..self.cleared()
+            }),
+            None => Err(MyError::Custom(format!(
+                "{} cells cannot change size, restart with another --cell",
+                C::size_name(&self.cell_max)
+            ))),
         }
     }
 
+    /// The same settings holding `view`, checked against the tape settings.
+    fn restored(&self, view: Vec<C>, ptr: usize, origin: usize) -> Result<Self, MyError> {
+        let error = |message: String| Err(MyError::Custom(message));
+        match self.tape {
+            Tape::Fixed(len) | Tape::Wrap(len) if view.len() != len as usize => {
+                return error(format!(
+                    "A tape of {} cells doesn't fit a tape of {} cells",
+                    view.len(),
+                    len
+                ))
+            }
+            Tape::Infinite => (),
+            _ if origin != 0 => {
+                return error("Only an infinite tape can grow to the left".to_string())
+            }
+            _ => (),
+        }
+        if ptr >= view.len() || origin >= view.len() {
+            return error(format!(
+                "A tape of {} cells has no cell {}",
+                view.len(),
+                ptr.max(origin)
+            ));
+        }
+        self.check_len(view.len())?;
+        Ok(Memory {
+            view,
+            ptr: ptr as u32,
+            origin: origin as u32,
+            ..self.cleared()
+        })
+    }
+
+    fn format_cell(&self, v: &C) -> String {
+        match self.signed {
+            true => v.to_signed_string(&self.cell_max),
+            false => v.to_string(),
+        }
+    }
+
+    /// Position of the pointer relative to the starting cell.
+    fn position(&self) -> i64 {
+        self.ptr as i64 - self.origin as i64
+    }
+
+    /// Value of the cell at `position`, 0 for cells not allocated yet.
+    fn get(&self, position: i64) -> C {
+        let index = match self.tape {
+            Tape::Wrap(len) => position.rem_euclid(len as i64),
+            _ => position + self.origin as i64,
+        };
+        usize::try_from(index)
+            .ok()
+            .and_then(|i| self.view.get(i))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Moves the pointer to `position`, growing the tape if needed.
+    fn move_to(&mut self, position: i64) -> Result<(), MyError> {
+        self.ptr = self.resolve(position - self.position())? as u32;
+        Ok(())
+    }
+
+    /// Stores `n` in the cell at `position`, growing the tape if needed.
+    fn set(&mut self, position: i64, n: u32) -> Result<(), MyError> {
+        let cell = C::from_u32(n, &self.cell_max).ok_or_else(|| {
+            MyError::Custom(format!(
+                "Value {} exceeds the maximum cell value {}",
+                n, self.cell_max
+            ))
+        })?;
+        let index = self.resolve(position - self.position())?;
+        self.view[index] = cell;
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn ptr_increase(&mut self, n: u32) -> Result<(), MyError> {
+        self.ptr = self.resolve(n as i64)? as u32;
+        Ok(())
+    }
+
+    #[inline(always)]
     fn ptr_decrease(&mut self, n: u32) -> Result<(), MyError> {
-        if n > self.ptr {
-            return Err(MyError::Custom(format!(
+        self.ptr = self.resolve(-(n as i64))? as u32;
+        Ok(())
+    }
+
+    /// Resolves `ptr + offset` to an index into `view`, growing the tape if needed.
+    #[inline(always)]
+    fn cell_index(&mut self, offset: i32) -> Result<usize, MyError> {
+        self.resolve(offset as i64)
+    }
+
+    /// Resolves `ptr + delta` to an index into `view` according to `tape`.
+    #[inline(always)]
+    fn resolve(&mut self, delta: i64) -> Result<usize, MyError> {
+        let index = self.ptr as i64 + delta;
+        // Cells already on the tape resolve to themselves in every mode
+        if 0 <= index && index < self.view.len() as i64 {
+            return Ok(index as usize);
+        }
+        self.resolve_outside(index, delta)
+    }
+
+    /// Handles an `index` outside `view`. Growing an infinite tape to the
+    /// left shifts `ptr` and `origin`.
+    #[cold]
+    fn resolve_outside(&mut self, index: i64, delta: i64) -> Result<usize, MyError> {
+        match self.tape {
+            Tape::Wrap(len) => Ok(index.rem_euclid(len as i64) as usize),
+            Tape::Infinite if index < 0 => {
+                // Double the tape, as far as `max_len` allows
+                let room = self.max_len.unwrap_or(usize::MAX) - self.view.len();
+                let grow = (index.unsigned_abs() as usize).max(self.view.len().min(room));
+                self.check_len(self.view.len() + grow)?;
+                self.view.splice(0..0, std::iter::repeat_n(C::default(), grow));
+                self.origin += grow as u32;
+                self.ptr += grow as u32;
+                Ok((index + grow as i64) as usize)
+            }
+            _ if index < 0 => Err(MyError::Custom(format!(
                 "The current pointer is at position {} and cannot move left by {} positions",
-                self.ptr, n
-            )));
+                self.position(),
+                delta.unsigned_abs()
+            ))),
+            Tape::Fixed(len) if index >= len as i64 => Err(MyError::Custom(format!(
+                "The current pointer is at position {} and cannot move right by {} positions on a tape of {} cells",
+                self.position(),
+                delta,
+                len
+            ))),
+            _ => {
+                let index = index as usize;
+                self.grow_to(index)?;
+                Ok(index)
+            }
+        }
+    }
+
+    /// Makes the tape long enough to hold the cell at `index`.
+    fn grow_to(&mut self, index: usize) -> Result<(), MyError> {
+        if self.view.len() <= index {
+            self.check_len(index + 1)?;
+            self.view.resize(index + 1, C::default());
         }
-        self.ptr -= n;
         Ok(())
     }
 
-    fn data_increase(&mut self, n: u32) {
-        let v = &mut self.view[self.ptr as usize];
-        *v = if self.cell_max - *v >= n {
-            *v + n
-        } else {
-            n - (self.cell_max - *v + 1)
-        };
+    fn check_len(&self, len: usize) -> Result<(), MyError> {
+        match self.max_len {
+            Some(max) if len > max => Err(MyError::MemoryLimit(max)),
+            _ => Ok(()),
+        }
     }
 
-    fn data_decrease(&mut self, n: u32) {
-        let v = &mut self.view[self.ptr as usize];
-        *v = if *v >= n {
-            *v - n
-        } else {
-            self.cell_max - (n - *v - 1)
-        };
+    #[inline(always)]
+    fn data_increase(&mut self, offset: i32, n: u32) -> Result<(), MyError> {
+        let index = self.cell_index(offset)?;
+        self.cell_add(index, n as i64)
+    }
+
+    #[inline(always)]
+    fn data_decrease(&mut self, offset: i32, n: u32) -> Result<(), MyError> {
+        let index = self.cell_index(offset)?;
+        self.cell_add(index, -(n as i64))
+    }
+
+    /// Adds `delta` to the cell at `index` according to `overflow`.
+    #[inline(always)]
+    fn cell_add(&mut self, index: usize, delta: i64) -> Result<(), MyError> {
+        match self.view[index].add(delta, &self.cell_max, self.overflow) {
+            Ok(()) => Ok(()),
+            Err(overflowed) => Err(self.overflow_error(index, overflowed, delta.unsigned_abs())),
+        }
     }
 
-    fn output(&self) -> u32 {
-        self.view[self.ptr as usize]
+    #[cold]
+    fn overflow_error(
+        &self,
+        index: usize,
+        overflowed: Overflowed,
+        delta: impl fmt::Display,
+    ) -> MyError {
+        let position = index as i64 - self.origin as i64;
+        let v = &self.view[index];
+        MyError::Custom(match overflowed {
+            Overflowed::Above => format!(
+                "Cell at position {} overflowed: {} + {} is greater than {}",
+                position, v, delta, self.cell_max
+            ),
+            Overflowed::Below => format!(
+                "Cell at position {} underflowed: {} - {} is less than 0",
+                position, v, delta
+            ),
+        })
     }
 
-    fn input(&mut self, n: u32) {
-        self.view[self.ptr as usize] = n;
+    fn set_zero(&mut self, offset: i32) -> Result<(), MyError> {
+        let index = self.cell_index(offset)?;
+        self.view[index] = C::default();
+        Ok(())
+    }
+
+    fn scan_right(&mut self, n: u32) -> Result<(), MyError> {
+        while !self.output().is_zero() {
+            self.ptr_increase(n)?;
+        }
+        Ok(())
+    }
+
+    fn scan_left(&mut self, n: u32) -> Result<(), MyError> {
+        while !self.output().is_zero() {
+            self.ptr_decrease(n)?;
+        }
+        Ok(())
+    }
+
+    /// Adds `factor` times the current cell to the cell at `ptr + offset`,
+    /// the same way repeated `+` / `-` would.
+    fn mul_add(&mut self, offset: i32, factor: i32) -> Result<(), MyError> {
+        if self.output().is_zero() {
+            return Ok(());
+        }
+        let v = self.output().clone();
+        let target = self.cell_index(offset)?;
+        match self.view[target].add_product(&v, factor, &self.cell_max, self.overflow) {
+            Ok(()) => Ok(()),
+            Err(overflowed) => Err(self.overflow_error(
+                target,
+                overflowed,
+                format!("{} * {}", v, factor.unsigned_abs()),
+            )),
+        }
+    }
+
+    fn output(&self) -> &C {
+        &self.view[self.ptr as usize]
+    }
+
+    fn output_at(&mut self, offset: i32) -> Result<&C, MyError> {
+        let index = self.cell_index(offset)?;
+        Ok(&self.view[index])
+    }
+
+    /// Stores the input `n`, which must fit in a cell.
+    fn input_u32(&mut self, offset: i32, n: u32) -> Result<(), MyError> {
+        match C::from_u32(n, &self.cell_max) {
+            Some(cell) => self.input(offset, cell),
+            None => Err(MyError::Custom(format!(
+                "Input value {} exceeds the maximum cell value {}",
+                n, self.cell_max
+            ))),
+        }
+    }
+
+    /// Stores what `eof` says `,` reads at the end of input.
+    fn input_eof(&mut self, offset: i32, eof: Eof) -> Result<(), MyError> {
+        match eof {
+            Eof::Zero => self.input(offset, C::default()),
+            Eof::Max => self.input(offset, C::minus_one(&self.cell_max)),
+            Eof::Unchanged => self.cell_index(offset).map(|_| ()),
+        }
+    }
+
+    fn input(&mut self, offset: i32, n: C) -> Result<(), MyError> {
+        let index = self.cell_index(offset)?;
+        self.view[index] = n;
+        Ok(())
+    }
+}
+
+/// Fills `config.token_vec` and `config.spans` from `config.raw_code`.
+fn compile_config(config: &mut Config) -> Result<(), MyError> {
+    (config.token_vec, config.spans) = compile(
+        &config.raw_code,
+        config.optimize,
+        arithmetic(config.cell_type != CellType::Big, config.overflow),
+        config.tape,
+    )?;
+    Ok(())
+}
+
+fn arithmetic(bounded: bool, overflow: Overflow) -> Arithmetic {
+    match (bounded, overflow) {
+        (true, Overflow::Wrap) => Arithmetic::Wrapping,
+        (true, _) => Arithmetic::Bounded,
+        (false, _) => Arithmetic::Unbounded,
     }
 }
 
-fn raw_code_to_token_vec(raw_code: &str) -> Result<Vec<Token>, MyError> {
+/// Returns the tokens and the source span of each token. `arithmetic` and
+/// `tape` tell the optimizer which loops it may replace, `tape` also which
+/// pointer moves it may fold.
+fn compile(
+    raw_code: &str,
+    optimize: bool,
+    arithmetic: Arithmetic,
+    tape: Tape,
+) -> Result<(Vec<Token>, Vec<Span>), MyError> {
+    let (token_vec, spans) = parse(raw_code, false)?;
+    Ok(match optimize {
+        true => {
+            let (token_vec, spans) = optimizer::optimize(token_vec, spans, arithmetic, tape);
+            optimizer::fold_offsets(token_vec, spans, tape)
+        }
+        false => (token_vec, spans),
+    })
+}
+
+/// Parses the code into tokens, together with the source span of each token.
+/// With `split_at_hash`, a '#' ends a run of `><+-` so that the debugger can
+/// stop between its two halves.
+fn parse(raw_code: &str, split_at_hash: bool) -> Result<(Vec<Token>, Vec<Span>), MyError> {
     let mut vec = Vec::new();
+    let mut spans: Vec<Span> = Vec::new();
     let mut stack = Vec::new();
     let chars = raw_code.chars();
+    // Whether a '#' came after the last token
+    let mut split = false;
 
     let mut line: u32 = 1;
     let mut col: u32 = 0;
     for char in chars {
         col += 1;
+        let pos = Pos { line, col };
+        let len = vec.len();
         match char {
             '>' => {
-                if let Some(Token::PtrIncrease(n)) = vec.last_mut() {
+                if let Some(Token::PtrIncrease(n)) = vec.last_mut().filter(|_| !split) {
                     *n += 1;
                 } else {
                     vec.push(Token::PtrIncrease(1));
                 }
             }
             '<' => {
-                if let Some(Token::PtrDecrease(n)) = vec.last_mut() {
+                if let Some(Token::PtrDecrease(n)) = vec.last_mut().filter(|_| !split) {
                     *n += 1;
                 } else {
                     vec.push(Token::PtrDecrease(1));
                 }
             }
             '+' => {
-                if let Some(Token::DataIncrease(n)) = vec.last_mut() {
+                if let Some(Token::DataIncrease { n, .. }) = vec.last_mut().filter(|_| !split) {
                     *n += 1;
                 } else {
-                    vec.push(Token::DataIncrease(1));
+                    vec.push(Token::DataIncrease { offset: 0, n: 1 });
                 }
             }
             '-' => {
-                if let Some(Token::DataDecrease(n)) = vec.last_mut() {
+                if let Some(Token::DataDecrease { n, .. }) = vec.last_mut().filter(|_| !split) {
                     *n += 1;
                 } else {
-                    vec.push(Token::DataDecrease(1));
+                    vec.push(Token::DataDecrease { offset: 0, n: 1 });
                 }
             }
-            '.' => vec.push(Token::Output),
-            ',' => vec.push(Token::Input),
+            '.' => vec.push(Token::Output { offset: 0 }),
+            ',' => vec.push(Token::Input { offset: 0 }),
             '[' => {
                 vec.push(Token::JumpForward(0));
                 stack.push(vec.len() as u32);
@@ -231,12 +828,22 @@ fn raw_code_to_token_vec(raw_code: &str) -> Result<Vec<Token>, MyError> {
                     }));
                 }
             }
+            '#' if split_at_hash => split = true,
             '\n' => {
                 line += 1;
                 col = 0;
             }
             _ => (),
         }
+        if vec.len() > len {
+            split = false;
+            spans.push(Span {
+                start: pos,
+                end: pos,
+            });
+        } else if matches!(char, '>' | '<' | '+' | '-') {
+            spans.last_mut().unwrap().end = pos;
+        }
     }
 
     if !stack.is_empty() {
@@ -247,7 +854,82 @@ fn raw_code_to_token_vec(raw_code: &str) -> Result<Vec<Token>, MyError> {
         }));
     }
 
-    Ok(vec)
+    Ok((vec, spans))
+}
+
+/// Prompts and reads a line, `None` at EOF.
+fn read_line_input(input: &mut dyn Read, prompt: bool) -> Result<Option<u32>, MyError> {
+    if prompt {
+        print!("Input:");
+        io::stdout().flush()?;
+    }
+    let mut line = Vec::new();
+    while let Some(byte) = read_byte(input)? {
+        line.push(byte);
+        if byte == b'\n' {
+            break;
+        }
+    }
+    let buffer = match String::from_utf8(line) {
+        Ok(buffer) => buffer,
+        Err(e) => return Err(MyError::Custom(format!("Invalid UTF-8 input: {}", e))),
+    };
+    let (n, eof) = input_to_u32(buffer)?;
+    if eof {
+        if prompt {
+            println!();
+        }
+        return Ok(None);
+    }
+    Ok(Some(n))
+}
+
+/// Reads a byte, or a whole UTF-8 encoded character when `utf8` is set.
+/// `None` at EOF.
+fn read_raw_input(input: &mut dyn Read, utf8: bool) -> Result<Option<u32>, MyError> {
+    let first = match read_byte(input)? {
+        Some(b) => b,
+        None => return Ok(None),
+    };
+    let len = match first {
+        _ if !utf8 => return Ok(Some(first as u32)),
+        0x00..=0x7F => return Ok(Some(first as u32)),
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => {
+            return Err(MyError::Custom(format!(
+                "Invalid UTF-8 input byte {}",
+                first
+            )))
+        }
+    };
+    let mut buffer = vec![first];
+    for _ in 1..len {
+        match read_byte(input)? {
+            Some(b) => buffer.push(b),
+            None => return Err(MyError::Custom("Incomplete UTF-8 input".to_string())),
+        }
+    }
+    match std::str::from_utf8(&buffer) {
+        Ok(s) => Ok(s.chars().next().map(|c| c as u32)),
+        Err(e) => Err(MyError::Custom(format!("Invalid UTF-8 input: {}", e))),
+    }
+}
+
+/// Reads a single byte, `None` at EOF. Never reads ahead, so the rest of the
+/// input stays available to the REPL. `Stdin` is buffered internally, so this
+/// doesn't read byte by byte from the OS.
+fn read_byte(input: &mut dyn Read) -> Result<Option<u8>, MyError> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(MyError::Io(e)),
+        }
+    }
 }
 
 fn input_to_u32(mut s: String) -> Result<(u32, bool), MyError> {
@@ -261,7 +943,7 @@ fn input_to_u32(mut s: String) -> Result<(u32, bool), MyError> {
     }
 
     match s.as_bytes() {
-        x if x.ends_with(&[13, 10]) => {
+        [.., 13, 10] => {
             // Windows
             match s.chars().count() {
                 3 => {
@@ -276,7 +958,7 @@ fn input_to_u32(mut s: String) -> Result<(u32, bool), MyError> {
             }
         }
 
-        x if x.ends_with(&[10]) => {
+        [.., 10] => {
             // Unix & Unix like
             match s.chars().count() {
                 2 => {
@@ -303,12 +985,206 @@ fn show_ir(token_vec: Vec<Token>) {
         match token {
             Token::PtrIncrease(n) => println!("PtrIncrease  {}", n),
             Token::PtrDecrease(n) => println!("PtrDecrease  {}", n),
-            Token::DataIncrease(n) => println!("DataIncrease {}", n),
-            Token::DataDecrease(n) => println!("DataDecrease {}", n),
+            Token::DataIncrease { offset, n } => println!("DataIncrease {}{}", n, at(offset)),
+            Token::DataDecrease { offset, n } => println!("DataDecrease {}{}", n, at(offset)),
             Token::JumpForward(n) => println!("JumpForward  {}", n),
             Token::JumpBack(n) => println!("JumpBack     {}", n),
-            Token::Output => println!("Output"),
-            Token::Input => println!("Input"),
+            Token::Output { offset } => println!("Output{}", at(offset)),
+            Token::Input { offset } => println!("Input{}", at(offset)),
+            Token::SetZero { offset } => println!("SetZero{}", at(offset)),
+            Token::ScanRight(n) => println!("ScanRight    {}", n),
+            Token::ScanLeft(n) => println!("ScanLeft     {}", n),
+            Token::MulAdd { offset, factor } => println!("MulAdd       {} {}", offset, factor),
+        }
+    }
+}
+
+/// Renders a cell offset relative to the pointer, e.g. ` @+2`, or nothing for 0.
+fn at(offset: i32) -> String {
+    match offset {
+        0 => String::new(),
+        _ => format!(" @{:+}", offset),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use errors::CompileErrorKind;
+
+    fn span(start: (u32, u32), end: (u32, u32)) -> Span {
+        Span {
+            start: Pos {
+                line: start.0,
+                col: start.1,
+            },
+            end: Pos {
+                line: end.0,
+                col: end.1,
+            },
+        }
+    }
+
+    #[test]
+    fn parse_merges_runs_and_links_jumps() {
+        let (tokens, _) = parse("++>[-]<.,", false).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::DataIncrease { offset: 0, n: 2 },
+                Token::PtrIncrease(1),
+                Token::JumpForward(5),
+                Token::DataDecrease { offset: 0, n: 1 },
+                Token::JumpBack(3),
+                Token::PtrDecrease(1),
+                Token::Output { offset: 0 },
+                Token::Input { offset: 0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_skips_comments_and_tracks_spans() {
+        let (tokens, spans) = parse("a ++ b\n  >>>\n[]", false).unwrap();
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(
+            spans,
+            vec![
+                span((1, 3), (1, 4)),
+                span((2, 3), (2, 5)),
+                span((3, 1), (3, 1)),
+                span((3, 2), (3, 2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_splits_runs_at_hash_for_the_debugger() {
+        let (tokens, _) = parse("++#++", false).unwrap();
+        assert_eq!(tokens, vec![Token::DataIncrease { offset: 0, n: 4 }]);
+        let (tokens, spans) = parse("++#++>#>", true).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::DataIncrease { offset: 0, n: 2 },
+                Token::DataIncrease { offset: 0, n: 2 },
+                Token::PtrIncrease(1),
+                Token::PtrIncrease(1),
+            ]
+        );
+        assert_eq!(spans[1], span((1, 4), (1, 5)));
+    }
+
+    #[test]
+    fn parse_rejects_unbalanced_brackets() {
+        for (code, kind) in [
+            ("+[", CompileErrorKind::UnclosedLeftBracket),
+            ("[[]", CompileErrorKind::UnclosedLeftBracket),
+            ("+]", CompileErrorKind::UnexpectedRightBracket),
+            ("[]]", CompileErrorKind::UnexpectedRightBracket),
+        ] {
+            match parse(code, false) {
+                Err(MyError::Compile(e)) => assert_eq!(
+                    std::mem::discriminant(&e.kind),
+                    std::mem::discriminant(&kind),
+                    "{code}"
+                ),
+                _ => panic!("{code} should not parse"),
+            }
         }
     }
+
+    fn memory<C: Cell>(cell_max: C, overflow: Overflow) -> Memory<C> {
+        Memory::new(cell_max, Tape::Grow, overflow, false, None)
+    }
+
+    fn check_wraparound<C: Cell + fmt::Debug>(cell_max: C) {
+        let mut mem = memory(cell_max.clone(), Overflow::Wrap);
+        mem.data_decrease(0, 1).unwrap();
+        assert_eq!(*mem.output(), cell_max);
+        mem.data_increase(0, 1).unwrap();
+        assert!(mem.output().is_zero());
+
+        // 2 * max wraps around to max - 1
+        mem.data_decrease(0, 1).unwrap();
+        mem.mul_add(1, 2).unwrap();
+        mem.ptr_increase(1).unwrap();
+        mem.data_increase(0, 1).unwrap();
+        assert_eq!(*mem.output(), cell_max);
+    }
+
+    #[test]
+    fn memory_wraps_around_at_every_cell_width() {
+        check_wraparound::<u32>(255);
+        check_wraparound::<u32>(65535);
+        check_wraparound::<u32>(u32::MAX);
+        check_wraparound::<u64>(u64::MAX);
+    }
+
+    #[test]
+    fn memory_saturates_and_traps() {
+        let mut mem = memory::<u32>(255, Overflow::Saturate);
+        mem.data_increase(0, 300).unwrap();
+        assert_eq!(*mem.output(), 255);
+        mem.data_decrease(0, 1000).unwrap();
+        assert_eq!(*mem.output(), 0);
+
+        let mut mem = memory::<u32>(255, Overflow::Trap);
+        assert!(mem.data_decrease(0, 1).is_err());
+        assert_eq!(*mem.output(), 0);
+        mem.data_increase(0, 255).unwrap();
+        assert!(mem.data_increase(0, 1).is_err());
+        assert_eq!(*mem.output(), 255);
+    }
+
+    #[test]
+    fn big_cells_never_wrap() {
+        let mut mem = memory(BigInt::default(), Overflow::Wrap);
+        mem.data_decrease(0, 1).unwrap();
+        assert_eq!(mem.output().to_string(), "-1");
+        mem.data_increase(0, u32::MAX).unwrap();
+        mem.data_increase(0, u32::MAX).unwrap();
+        assert_eq!(mem.output().to_string(), "8589934589");
+    }
+
+    #[test]
+    fn memory_moves_and_sets_by_position() {
+        let mut mem = Memory::new(255u32, Tape::Infinite, Overflow::Wrap, false, None);
+        mem.set(3, 65).unwrap();
+        assert_eq!(mem.get(3), 65);
+        assert!(mem.set(0, 256).is_err());
+        mem.move_to(-2).unwrap();
+        assert_eq!(mem.position(), -2);
+        assert_eq!(mem.get(3), 65);
+        mem.move_to(3).unwrap();
+        assert_eq!(*mem.output(), 65);
+    }
+
+    #[test]
+    fn input_to_u32_parses_characters() {
+        let parse = |s: &str| input_to_u32(s.to_string()).unwrap();
+        assert_eq!(parse("a\n"), ('a' as u32, false));
+        assert_eq!(parse("a\r\n"), ('a' as u32, false));
+        assert_eq!(parse("é\n"), ('é' as u32, false));
+        assert_eq!(parse("\n"), ('\n' as u32, false));
+        assert_eq!(parse("\r\n"), ('\n' as u32, false));
+        assert_eq!(parse(""), (0, true));
+    }
+
+    #[test]
+    fn input_to_u32_parses_numbers() {
+        assert_eq!(input_to_u32("65u32\n".to_string()).unwrap(), (65, false));
+        assert_eq!(
+            input_to_u32("4294967295u32\n".to_string()).unwrap(),
+            (u32::MAX, false)
+        );
+        assert!(input_to_u32("4294967296u32\n".to_string()).is_err());
+        assert!(input_to_u32("-1u32\n".to_string()).is_err());
+    }
+
+    #[test]
+    fn input_to_u32_rejects_several_characters() {
+        assert!(input_to_u32("ab\n".to_string()).is_err());
+        assert!(input_to_u32("ab\r\n".to_string()).is_err());
+    }
 }
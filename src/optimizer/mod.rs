@@ -0,0 +1,292 @@
+use crate::{Span, Tape, Token};
+
+/// What the optimizer may assume about cell arithmetic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arithmetic {
+    /// Cells wrap around past 0 and their maximum.
+    Wrapping,
+    /// Cells stay between 0 and their maximum, saturating or trapping.
+    Bounded,
+    /// Big cells, which also go negative.
+    Unbounded,
+}
+
+/// Rewrites common loop idioms into dedicated tokens.
+///
+/// `[-]` / `[+]` become `SetZero`, `[>]` / `[<<]` become `ScanRight` / `ScanLeft`,
+/// and balanced transfer loops like `[->+>++<<]` become a series of `MulAdd`
+/// followed by `SetZero`. Jump targets are relinked afterwards.
+///
+/// `spans` runs parallel to `token_vec`; tokens that replace a loop take the
+/// span of the whole loop. `[+]` only counts as `SetZero` and transfer loops
+/// only become `MulAdd` when cells wrap: under saturate or trap the loop's
+/// intermediate values decide the result, not just its net effect. `[-]`
+/// needs cells that can't go negative, where it would never end. Scans stay
+/// loops on a wrapping tape, where they may never end either, so that each
+/// of their steps still counts against `--max-steps` and `--timeout`.
+pub fn optimize(
+    token_vec: Vec<Token>,
+    spans: Vec<Span>,
+    arithmetic: Arithmetic,
+    tape: Tape,
+) -> (Vec<Token>, Vec<Span>) {
+    let mut result = Vec::with_capacity(token_vec.len());
+    let mut result_spans = Vec::with_capacity(spans.len());
+    let mut i = 0;
+    while i < token_vec.len() {
+        if let Token::JumpForward(end) = token_vec[i] {
+            let body = &token_vec[i + 1..end as usize - 1];
+            if let Some(tokens) = optimize_loop(body, arithmetic, tape) {
+                let span = spans[i].to(spans[end as usize - 1]);
+                result_spans.extend(tokens.iter().map(|_| span));
+                result.extend(tokens);
+                i = end as usize;
+                continue;
+            }
+        }
+        result.push(token_vec[i]);
+        result_spans.push(spans[i]);
+        i += 1;
+    }
+    link_jumps(&mut result);
+    (result, result_spans)
+}
+
+fn optimize_loop(body: &[Token], arithmetic: Arithmetic, tape: Tape) -> Option<Vec<Token>> {
+    let wrapping = arithmetic == Arithmetic::Wrapping;
+    let scans = !matches!(tape, Tape::Wrap(_));
+    match body {
+        [Token::DataIncrease { n: 1, .. }] if wrapping => Some(vec![Token::SetZero { offset: 0 }]),
+        [Token::DataDecrease { n: 1, .. }] if arithmetic != Arithmetic::Unbounded => {
+            Some(vec![Token::SetZero { offset: 0 }])
+        }
+        [Token::PtrIncrease(n)] if scans => Some(vec![Token::ScanRight(*n)]),
+        [Token::PtrDecrease(n)] if scans => Some(vec![Token::ScanLeft(*n)]),
+        _ if wrapping => optimize_mul_loop(body),
+        _ => None,
+    }
+}
+
+/// Matches a loop body made only of `+-<>` that returns to its starting cell
+/// and decreases that cell by exactly one per iteration.
+fn optimize_mul_loop(body: &[Token]) -> Option<Vec<Token>> {
+    let mut offset: i64 = 0;
+    // (offset, accumulated change) in order of first appearance
+    let mut changes: Vec<(i64, i64)> = Vec::new();
+
+    for &token in body {
+        match token {
+            Token::PtrIncrease(n) => offset += n as i64,
+            Token::PtrDecrease(n) => offset -= n as i64,
+            Token::DataIncrease { n, .. } => add_change(&mut changes, offset, n as i64),
+            Token::DataDecrease { n, .. } => add_change(&mut changes, offset, -(n as i64)),
+            _ => return None,
+        }
+    }
+
+    if offset != 0 {
+        return None;
+    }
+
+    let mut source_change = 0;
+    let mut result = Vec::new();
+    for (offset, change) in changes {
+        if offset == 0 {
+            source_change = change;
+        } else if change != 0 {
+            result.push(Token::MulAdd {
+                offset: i32::try_from(offset).ok()?,
+                factor: i32::try_from(change).ok()?,
+            });
+        }
+    }
+
+    if source_change != -1 {
+        return None;
+    }
+    result.push(Token::SetZero { offset: 0 });
+    Some(result)
+}
+
+/// Folds pointer moves into the offsets of the data, output and input tokens
+/// that follow them, emitting the accumulated move only at the end of each
+/// basic block (before a jump, a scan, a `MulAdd` or the end of the program).
+///
+/// Expects tokens straight from `optimize`, i.e. with every offset still 0.
+/// A token that absorbed pointer moves has its span extended back over them.
+/// A move that may go past an end of `tape` is never folded: the block is
+/// flushed and the move kept as it is, so that it fails at the same position
+/// and with the same distance as without folding.
+pub fn fold_offsets(
+    token_vec: Vec<Token>,
+    spans: Vec<Span>,
+    tape: Tape,
+) -> (Vec<Token>, Vec<Span>) {
+    let mut result = Folded {
+        tokens: Vec::with_capacity(token_vec.len()),
+        spans: Vec::with_capacity(spans.len()),
+        offset: 0,
+        moves: None,
+    };
+
+    for (token, span) in token_vec.into_iter().zip(spans) {
+        match token {
+            Token::PtrIncrease(n) => {
+                match i32::try_from(n)
+                    .ok()
+                    .and_then(|n| result.offset.checked_add(n))
+                {
+                    Some(o) if !may_fail(tape, o) => result.move_to(o, span),
+                    _ => {
+                        result.flush();
+                        result.push(token, span);
+                    }
+                }
+            }
+            Token::PtrDecrease(n) => {
+                match i32::try_from(n)
+                    .ok()
+                    .and_then(|n| result.offset.checked_sub(n))
+                {
+                    Some(o) if !may_fail(tape, o) => result.move_to(o, span),
+                    _ => {
+                        result.flush();
+                        result.push(token, span);
+                    }
+                }
+            }
+            Token::DataIncrease { n, .. } => {
+                let offset = result.offset;
+                match result.tokens.last_mut() {
+                    Some(Token::DataIncrease { offset: o, n: last }) if *o == offset => {
+                        *last += n;
+                        result.extend_last(span);
+                    }
+                    _ => result.push_at_offset(Token::DataIncrease { offset, n }, span),
+                }
+            }
+            Token::DataDecrease { n, .. } => {
+                let offset = result.offset;
+                match result.tokens.last_mut() {
+                    Some(Token::DataDecrease { offset: o, n: last }) if *o == offset => {
+                        *last += n;
+                        result.extend_last(span);
+                    }
+                    _ => result.push_at_offset(Token::DataDecrease { offset, n }, span),
+                }
+            }
+            Token::Output { .. } => result.push_at_offset(
+                Token::Output {
+                    offset: result.offset,
+                },
+                span,
+            ),
+            Token::Input { .. } => result.push_at_offset(
+                Token::Input {
+                    offset: result.offset,
+                },
+                span,
+            ),
+            Token::SetZero { .. } => result.push_at_offset(
+                Token::SetZero {
+                    offset: result.offset,
+                },
+                span,
+            ),
+            Token::JumpForward(_)
+            | Token::JumpBack(_)
+            | Token::ScanRight(_)
+            | Token::ScanLeft(_)
+            | Token::MulAdd { .. } => {
+                result.flush();
+                result.push(token, span);
+            }
+        }
+    }
+    result.flush();
+
+    link_jumps(&mut result.tokens);
+    (result.tokens, result.spans)
+}
+
+/// Whether moving to `offset` from the start of a block may go past an end
+/// of `tape`.
+fn may_fail(tape: Tape, offset: i32) -> bool {
+    match tape {
+        Tape::Grow => offset < 0,
+        Tape::Fixed(_) => offset != 0,
+        Tape::Wrap(_) | Tape::Infinite => false,
+    }
+}
+
+/// Output of `fold_offsets` together with the pointer move not emitted yet.
+struct Folded {
+    tokens: Vec<Token>,
+    spans: Vec<Span>,
+    offset: i32,
+    /// Span of the pointer moves folded into `offset`.
+    moves: Option<Span>,
+}
+
+impl Folded {
+    fn push(&mut self, token: Token, span: Span) {
+        self.tokens.push(token);
+        self.spans.push(span);
+    }
+
+    /// Pushes a token that reads or writes the cell at `offset`.
+    fn push_at_offset(&mut self, token: Token, span: Span) {
+        match self.moves {
+            Some(moves) if self.offset != 0 => self.push(token, moves.to(span)),
+            _ => self.push(token, span),
+        }
+    }
+
+    fn extend_last(&mut self, span: Span) {
+        let last = self.spans.last_mut().unwrap();
+        *last = last.to(span);
+    }
+
+    fn move_to(&mut self, offset: i32, span: Span) {
+        self.offset = offset;
+        self.moves = Some(match self.moves {
+            Some(moves) => moves.to(span),
+            None => span,
+        });
+    }
+
+    fn flush(&mut self) {
+        if let Some(moves) = self.moves.take() {
+            match self.offset {
+                0 => (),
+                o if o > 0 => self.push(Token::PtrIncrease(o as u32), moves),
+                o => self.push(Token::PtrDecrease(o.unsigned_abs()), moves),
+            }
+        }
+        self.offset = 0;
+    }
+}
+
+fn add_change(changes: &mut Vec<(i64, i64)>, offset: i64, n: i64) {
+    match changes.iter_mut().find(|(o, _)| *o == offset) {
+        Some((_, change)) => *change += n,
+        None => changes.push((offset, n)),
+    }
+}
+
+/// Recomputes the targets of every `JumpForward` / `JumpBack` pair,
+/// using the same convention as `parse`.
+fn link_jumps(token_vec: &mut [Token]) {
+    let mut stack = Vec::new();
+    for i in 0..token_vec.len() {
+        match token_vec[i] {
+            Token::JumpForward(_) => stack.push(i),
+            Token::JumpBack(_) => {
+                let start = stack.pop().expect("brackets are balanced after parsing");
+                token_vec[i] = Token::JumpBack(start as u32 + 1);
+                token_vec[start] = Token::JumpForward(i as u32 + 1);
+            }
+            _ => (),
+        }
+    }
+}
@@ -1,6 +1,15 @@
 use crate::errors::MyError;
+use crate::CellType;
+use crate::EmitTarget;
+use crate::Eof;
+use crate::InputMode;
+use crate::OutputEncoding;
 use crate::OutputMode;
-use crate::Token;
+use crate::Overflow;
+use crate::Tape;
+use crate::{Span, Token};
+use std::io::{self, IsTerminal};
+use std::time::Duration;
 use std::{env::Args, fs};
 
 static HELP: &str = "Usage:
@@ -8,20 +17,69 @@ static HELP: &str = "Usage:
 [code]              Use an argument as the code
 -f [path]           Read code from a file
 --REPL | --repl     Start in REPL mode
+--session [path]    Restore a REPL session saved with :save
 --bulk              Bulk output mode
---cell [u8|u16|u32] Set the cell size
+--cell [u8|u16|u32|u64|big]
+                    Set the cell size, big cells are signed and unbounded
+--overflow [wrap|saturate|trap]
+                    Set what happens when a cell goes past 0 or its maximum
+                    (default wrap)
+--signed            Display cells as signed values
 -v | --verbose      Display verbose information
 --IR                Display intermediate representation of the code
+--no-opt            Disable the optimization pass
+--emit [c|wat|llvm|asm|rust]
+                    Compile the code to another language instead of running it
+-o [path]           Write the --emit output to a file instead of stdout
+--tape [cells|infinite]
+                    Use a fixed tape of that many cells, both when running
+                    and with --emit, or one that also grows to the left
+                    (default grows to the right, 30000 cells for --emit wat|asm)
+--wrap              Wrap around at the ends of the tape (default 30000 cells)
+--jit               Run with the x86-64 JIT (requires the `jit` feature)
+--debug             Run in the step debugger, '#' in the code sets a breakpoint
+--profile           Print the hottest loops to stderr when the program exits
+--eof [zero|max|unchanged]
+                    Set what ',' stores at the end of input (default zero)
+--input [line|raw]  Read a prompted line or a raw byte per ','
+                    (default line when stdin is a terminal, raw otherwise)
+--out [utf8|latin1|bytes]
+                    Write each '.' as UTF-8, as a Latin-1 byte or as the
+                    raw low 8 bits of the cell (default utf8)
+--max-steps [n]     Stop with an error after n steps
+--timeout [duration]
+                    Stop with an error after the duration, e.g. 500ms, 10s, 2m
+--max-memory [cells]
+                    Stop with an error if the tape needs more cells
 [input]             Input can be a string ending with 'u32' to be parsed as uint32";
 
 pub struct Config {
     pub raw_code: String,
     pub output_mode: OutputMode,
     pub token_vec: Vec<Token>,
+    pub spans: Vec<Span>,
     pub cell_max: u32,
+    pub cell_type: CellType,
     pub verbose: bool,
     pub repl_mode: bool,
+    pub session: Option<String>,
     pub show_ir: bool,
+    pub optimize: bool,
+    pub emit: Option<EmitTarget>,
+    pub output_path: Option<String>,
+    pub jit: bool,
+    pub tape_len: u32,
+    pub tape: Tape,
+    pub overflow: Overflow,
+    pub signed: bool,
+    pub debug: bool,
+    pub profile: bool,
+    pub eof: Eof,
+    pub input_mode: InputMode,
+    pub output_encoding: OutputEncoding,
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub max_memory: Option<usize>,
 }
 
 impl Default for Config {
@@ -36,10 +94,29 @@ impl Config {
             raw_code: String::new(),
             output_mode: OutputMode::Individually,
             token_vec: Vec::new(),
+            spans: Vec::new(),
             cell_max: 255,
+            cell_type: CellType::U32,
             verbose: false,
             repl_mode: false,
+            session: None,
             show_ir: false,
+            optimize: true,
+            emit: None,
+            output_path: None,
+            jit: false,
+            tape_len: 30000,
+            tape: Tape::Grow,
+            overflow: Overflow::Wrap,
+            signed: false,
+            debug: false,
+            profile: false,
+            eof: Eof::Zero,
+            input_mode: InputMode::Line,
+            output_encoding: OutputEncoding::Utf8,
+            max_steps: None,
+            timeout: None,
+            max_memory: None,
         }
     }
 
@@ -47,6 +124,8 @@ impl Config {
         let mut config = Config::new();
         let args: Vec<String> = args.skip(1).collect();
         let mut args_iter = args.into_iter();
+        let mut input_mode = None;
+        let mut wrap = false;
 
         while let Some(arg) = args_iter.next() {
             match arg.as_str() {
@@ -77,6 +156,8 @@ impl Config {
                             "u8" => config.cell_max = 255,
                             "u16" => config.cell_max = 65535,
                             "u32" => config.cell_max = 4294967295,
+                            "u64" => config.cell_type = CellType::U64,
+                            "big" => config.cell_type = CellType::Big,
                             _ => {
                                 return Err(MyError::Custom(format!(
                                     "Invalid cell size type {cell_size_type}"
@@ -87,10 +168,143 @@ impl Config {
                         return Err(MyError::Custom("Cell size type not specified".to_string()));
                     }
                 }
+                "--overflow" => {
+                    if let Some(overflow) = args_iter.next() {
+                        match overflow.as_str() {
+                            "wrap" => config.overflow = Overflow::Wrap,
+                            "saturate" => config.overflow = Overflow::Saturate,
+                            "trap" => config.overflow = Overflow::Trap,
+                            _ => {
+                                return Err(MyError::Custom(format!(
+                                    "Invalid overflow policy {overflow}"
+                                )))
+                            }
+                        }
+                    } else {
+                        return Err(MyError::Custom("Overflow policy not specified".to_string()));
+                    }
+                }
+                "--signed" => config.signed = true,
                 "--REPL" | "--repl" => {
                     config.repl_mode = true;
                 }
+                "--session" => {
+                    if let Some(path) = args_iter.next() {
+                        config.session = Some(path);
+                    } else {
+                        return Err(MyError::Custom("Session path not specified".to_string()));
+                    }
+                }
                 "--IR" => config.show_ir = true,
+                "--no-opt" => config.optimize = false,
+                "--emit" => {
+                    if let Some(target) = args_iter.next() {
+                        match target.as_str() {
+                            "c" => config.emit = Some(EmitTarget::C),
+                            "wat" => config.emit = Some(EmitTarget::Wat),
+                            "llvm" => config.emit = Some(EmitTarget::Llvm),
+                            "asm" => config.emit = Some(EmitTarget::Asm),
+                            "rust" => config.emit = Some(EmitTarget::Rust),
+                            _ => {
+                                return Err(MyError::Custom(format!(
+                                    "Invalid emit target {target}"
+                                )))
+                            }
+                        }
+                    } else {
+                        return Err(MyError::Custom("Emit target not specified".to_string()));
+                    }
+                }
+                "--jit" => config.jit = true,
+                "--debug" => config.debug = true,
+                "--profile" => config.profile = true,
+                "--input" => {
+                    if let Some(mode) = args_iter.next() {
+                        match mode.as_str() {
+                            "line" => input_mode = Some(InputMode::Line),
+                            "raw" => input_mode = Some(InputMode::Raw),
+                            _ => return Err(MyError::Custom(format!("Invalid input mode {mode}"))),
+                        }
+                    } else {
+                        return Err(MyError::Custom("Input mode not specified".to_string()));
+                    }
+                }
+                "--out" => {
+                    if let Some(encoding) = args_iter.next() {
+                        match encoding.as_str() {
+                            "utf8" => config.output_encoding = OutputEncoding::Utf8,
+                            "latin1" => config.output_encoding = OutputEncoding::Latin1,
+                            "bytes" => config.output_encoding = OutputEncoding::Bytes,
+                            _ => {
+                                return Err(MyError::Custom(format!(
+                                    "Invalid output encoding {encoding}"
+                                )))
+                            }
+                        }
+                    } else {
+                        return Err(MyError::Custom("Output encoding not specified".to_string()));
+                    }
+                }
+                "--eof" => {
+                    if let Some(eof) = args_iter.next() {
+                        match eof.as_str() {
+                            "zero" => config.eof = Eof::Zero,
+                            "max" => config.eof = Eof::Max,
+                            "unchanged" => config.eof = Eof::Unchanged,
+                            _ => {
+                                return Err(MyError::Custom(format!("Invalid EOF behavior {eof}")))
+                            }
+                        }
+                    } else {
+                        return Err(MyError::Custom("EOF behavior not specified".to_string()));
+                    }
+                }
+                "--tape" => {
+                    if let Some(tape_len) = args_iter.next() {
+                        if tape_len == "infinite" {
+                            config.tape = Tape::Infinite;
+                            continue;
+                        }
+                        config.tape_len = tape_len.parse()?;
+                        if config.tape_len == 0 {
+                            return Err(MyError::Custom(
+                                "Tape length must be positive".to_string(),
+                            ));
+                        }
+                        config.tape = Tape::Fixed(config.tape_len);
+                    } else {
+                        return Err(MyError::Custom("Tape length not specified".to_string()));
+                    }
+                }
+                "--wrap" => wrap = true,
+                "--max-steps" => {
+                    if let Some(max_steps) = args_iter.next() {
+                        config.max_steps = Some(max_steps.parse()?);
+                    } else {
+                        return Err(MyError::Custom("Step limit not specified".to_string()));
+                    }
+                }
+                "--timeout" => {
+                    if let Some(timeout) = args_iter.next() {
+                        config.timeout = Some(parse_duration(&timeout)?);
+                    } else {
+                        return Err(MyError::Custom("Timeout not specified".to_string()));
+                    }
+                }
+                "--max-memory" => {
+                    if let Some(max_memory) = args_iter.next() {
+                        config.max_memory = Some(max_memory.parse()?);
+                    } else {
+                        return Err(MyError::Custom("Memory limit not specified".to_string()));
+                    }
+                }
+                "-o" => {
+                    if let Some(output_path) = args_iter.next() {
+                        config.output_path = Some(output_path);
+                    } else {
+                        return Err(MyError::Custom("Output path not specified".to_string()));
+                    }
+                }
 
                 _ => {
                     if arg.starts_with('-') {
@@ -105,10 +319,162 @@ impl Config {
             }
         }
 
+        if config.output_path.is_some() && config.emit.is_none() {
+            return Err(MyError::Custom(
+                "Output path can only be used with --emit".to_string(),
+            ));
+        }
+
+        config.input_mode = input_mode.unwrap_or(match io::stdin().is_terminal() {
+            true => InputMode::Line,
+            false => InputMode::Raw,
+        });
+
+        if wrap {
+            if config.tape == Tape::Infinite {
+                return Err(MyError::Custom(
+                    "--wrap cannot be combined with an infinite tape".to_string(),
+                ));
+            }
+            config.tape = Tape::Wrap(config.tape_len);
+        }
+
+        config.check_backend()?;
+
+        if config.eof != Eof::Zero && config.emit.is_some() {
+            return Err(MyError::Custom(
+                "--eof cannot be combined with --emit".to_string(),
+            ));
+        }
+
+        if config.output_encoding != OutputEncoding::Utf8 && config.emit.is_some() {
+            return Err(MyError::Custom(
+                "--out cannot be combined with --emit".to_string(),
+            ));
+        }
+
+        if let (Some(max_memory), Tape::Fixed(len) | Tape::Wrap(len)) =
+            (config.max_memory, config.tape)
+        {
+            if len as usize > max_memory {
+                return Err(MyError::Custom(format!(
+                    "A tape of {len} cells exceeds --max-memory {max_memory}"
+                )));
+            }
+        }
+
+        if (config.max_steps.is_some() || config.timeout.is_some()) && config.jit {
+            return Err(MyError::Custom(
+                "--max-steps and --timeout cannot be combined with --jit".to_string(),
+            ));
+        }
+
+        // The debugger waits for commands between steps
+        if (config.max_steps.is_some() || config.timeout.is_some()) && config.debug {
+            return Err(MyError::Custom(
+                "--max-steps and --timeout cannot be combined with --debug".to_string(),
+            ));
+        }
+
+        if (config.max_steps.is_some() || config.timeout.is_some() || config.max_memory.is_some())
+            && config.emit.is_some()
+        {
+            return Err(MyError::Custom(
+                "--max-steps, --timeout and --max-memory cannot be combined with --emit"
+                    .to_string(),
+            ));
+        }
+
+        if config.jit && (config.verbose || config.repl_mode) {
+            return Err(MyError::Custom(
+                "--jit cannot be combined with --verbose or --repl".to_string(),
+            ));
+        }
+
+        if config.debug && (config.jit || config.repl_mode) {
+            return Err(MyError::Custom(
+                "--debug cannot be combined with --jit or --repl".to_string(),
+            ));
+        }
+
+        if config.profile && (config.verbose || config.repl_mode || config.debug || config.jit) {
+            return Err(MyError::Custom(
+                "--profile cannot be combined with --verbose, --repl, --debug or --jit".to_string(),
+            ));
+        }
+
+        if config.session.is_some() && !config.repl_mode {
+            return Err(MyError::Custom("--session requires --repl".to_string()));
+        }
+
         if config.raw_code.is_empty() && !config.repl_mode {
             return Err(MyError::Custom("No code found".to_string()));
         }
 
         Ok(config)
     }
+
+    /// Rejects the tape, overflow and cell settings that the JIT and `--emit`
+    /// don't implement. Also checked before running, for a `Config` that
+    /// wasn't parsed from the command line.
+    pub(crate) fn check_backend(&self) -> Result<(), MyError> {
+        if self.emit.is_some() && matches!(self.tape, Tape::Wrap(_) | Tape::Infinite) {
+            return Err(MyError::Custom(
+                "--emit only supports a tape of fixed length".to_string(),
+            ));
+        }
+
+        if self.jit && self.tape != Tape::Grow {
+            return Err(MyError::Custom(
+                "--jit only supports the default tape".to_string(),
+            ));
+        }
+
+        if self.overflow != Overflow::Wrap && (self.jit || self.emit.is_some()) {
+            return Err(MyError::Custom(
+                "--jit and --emit only support wrapping cells".to_string(),
+            ));
+        }
+
+        // Both wrap cells at the full width of their type, so `cell_max`
+        // can't be any other value
+        let native =
+            self.cell_type == CellType::U32 && matches!(self.cell_max, 255 | 65535 | 4294967295);
+        if !native && (self.jit || self.emit.is_some()) {
+            return Err(MyError::Custom(
+                "--jit and --emit only support u8, u16 and u32 cells".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a duration such as `500ms`, `10s` or `2m`, plain numbers are seconds.
+fn parse_duration(s: &str) -> Result<Duration, MyError> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (n, unit) = s.split_at(split);
+    let n: u64 = n.parse()?;
+    let invalid = || MyError::Custom(format!("Invalid duration {s}"));
+    match unit {
+        "ms" => Ok(Duration::from_millis(n)),
+        "" | "s" => Ok(Duration::from_secs(n)),
+        "m" => Ok(Duration::from_secs(n.checked_mul(60).ok_or_else(invalid)?)),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_duration;
+    use std::time::Duration;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("10").unwrap(), Duration::from_secs(10));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        assert!(parse_duration("2h").is_err());
+        assert!(parse_duration("999999999999999999m").is_err());
+    }
 }
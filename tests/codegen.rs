@@ -0,0 +1,204 @@
+//! Builds the `--emit` output of the programs in `example/` with the usual
+//! tool for each target and compares what it prints with `Interpreter`,
+//! given the same input on a pipe. Targets whose tool isn't installed are
+//! skipped.
+
+use brainfuck_hcy::{Config, EmitTarget, Interpreter, Tape};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The targets that build into a native program, with the tool that builds them.
+const TARGETS: [(EmitTarget, &str); 4] = [
+    (EmitTarget::Rust, "rustc"),
+    (EmitTarget::C, "cc"),
+    (EmitTarget::Llvm, "llc"),
+    (EmitTarget::Asm, "as"),
+];
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// A directory of its own for the generated files of `test` in this run.
+fn work_dir(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("brainfuck-codegen-{}-{test}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn installed(tool: &str) -> bool {
+    let found = Command::new(tool)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok();
+    if !found {
+        eprintln!("{tool} not found, skipping its target");
+    }
+    found
+}
+
+fn succeeds(command: &mut Command) -> bool {
+    command.status().unwrap().success()
+}
+
+/// LLVM 14 only reads the `ptr` type with -opaque-pointers, later versions
+/// always do and dropped the flag.
+fn llc_flags() -> &'static [&'static str] {
+    let version = Command::new("llc").arg("--version").output().unwrap();
+    match String::from_utf8_lossy(&version.stdout).contains("LLVM version 14.") {
+        true => &["-opaque-pointers"],
+        false => &[],
+    }
+}
+
+/// Emits `code` for `target` with the settings of `configure` and builds it.
+fn compile(
+    target: EmitTarget,
+    code: &str,
+    dir: &Path,
+    name: &str,
+    configure: fn(&mut Config),
+) -> PathBuf {
+    let extension = match target {
+        EmitTarget::Rust => "rs",
+        EmitTarget::C => "c",
+        EmitTarget::Llvm => "ll",
+        EmitTarget::Asm => "s",
+        EmitTarget::Wat => unreachable!("wat doesn't build into a program"),
+    };
+    let source = dir.join(format!("{name}.{extension}"));
+    let binary = dir.join(name);
+    let mut config = Config::new();
+    config.raw_code = code.to_string();
+    config.emit = Some(target);
+    config.output_path = Some(source.to_str().unwrap().to_string());
+    configure(&mut config);
+    brainfuck_hcy::run(config).unwrap();
+
+    let built = match target {
+        EmitTarget::Rust => succeeds(
+            Command::new("rustc")
+                .args(["--edition", "2021", "-o"])
+                .arg(&binary)
+                .arg(&source),
+        ),
+        EmitTarget::C => succeeds(Command::new("cc").arg("-o").arg(&binary).arg(&source)),
+        EmitTarget::Llvm => {
+            let object = dir.join(format!("{name}.o"));
+            succeeds(
+                Command::new("llc")
+                    .args(llc_flags())
+                    .args(["-relocation-model=pic", "-filetype=obj", "-o"])
+                    .arg(&object)
+                    .arg(&source),
+            ) && succeeds(Command::new("cc").arg("-o").arg(&binary).arg(&object))
+        }
+        EmitTarget::Asm => {
+            let object = dir.join(format!("{name}.o"));
+            succeeds(Command::new("as").arg(&source).arg("-o").arg(&object))
+                && succeeds(Command::new("ld").arg(&object).arg("-o").arg(&binary))
+        }
+        EmitTarget::Wat => unreachable!(),
+    };
+    assert!(built, "{name}.{extension} doesn't build");
+    binary
+}
+
+/// Emits `code` for `target`, builds it and runs it with `input` on stdin.
+fn run_compiled(target: EmitTarget, code: &str, input: &[u8], dir: &Path, name: &str) -> Vec<u8> {
+    let mut child = Command::new(compile(target, code, dir, name, |_| ()))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{name} failed");
+    output.stdout
+}
+
+/// Checks that `code` prints the same compiled for every target as through
+/// `Interpreter`.
+fn check(code: &str, input: &[u8], dir: &Path, name: &str) {
+    let mut expected = Vec::new();
+    Interpreter::new(code)
+        .input(input)
+        .output(&mut expected)
+        .run()
+        .unwrap();
+    for (target, tool) in TARGETS {
+        if !installed(tool) {
+            continue;
+        }
+        let output = run_compiled(target, code, input, dir, &format!("{name}-{tool}"));
+        assert!(
+            output == expected,
+            "{name}.bf compiled with {tool} prints differently:\n{}",
+            String::from_utf8_lossy(&output)
+        );
+    }
+}
+
+#[test]
+fn compiled_examples_match_the_interpreter() {
+    let dir = work_dir("examples");
+    for entry in fs::read_dir(root().join("example")).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_stem().unwrap().to_str().unwrap();
+        // mandel takes minutes on the unoptimized interpreter, see
+        // tests/examples.rs, and rustc takes about a minute on hanoi
+        if name == "mandel" || name == "hanoi" {
+            continue;
+        }
+        let code = fs::read_to_string(&path).unwrap();
+        let input_path = root()
+            .join("tests")
+            .join("golden")
+            .join(format!("{name}.in"));
+        let input = fs::read(input_path).unwrap_or_default();
+        check(&code, &input, &dir, name);
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// u8 cells read a byte per `,`, even one that starts a UTF-8 sequence.
+#[test]
+fn compiled_input_reads_bytes_into_u8_cells() {
+    let dir = work_dir("bytes");
+    check(",.,.", b"\xe9A", &dir, "bytes");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn compiled_programs_stop_at_the_end_of_a_fixed_tape() {
+    let dir = work_dir("fixed");
+    for (target, tool) in TARGETS {
+        // asm always has a fixed tape and reports its end more briefly
+        if target == EmitTarget::Asm || !installed(tool) {
+            continue;
+        }
+        let binary = compile(
+            target,
+            ">>>+[>+]",
+            &dir,
+            &format!("fixed-{tool}"),
+            |config| {
+                config.tape = Tape::Fixed(4);
+                config.tape_len = 4;
+            },
+        );
+        let output = Command::new(binary).stdin(Stdio::null()).output().unwrap();
+        assert!(!output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            "Error: The current pointer is at position 3 and cannot move right by 1 positions on a tape of 4 cells\n",
+            "{tool}"
+        );
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,138 @@
+//! Runs the programs in `example/` through `Interpreter` and compares their
+//! output to `tests/golden/<name>.out`, with `tests/golden/<name>.in` as
+//! input when it exists.
+
+use brainfuck_hcy::{Config, InputMode, Interpreter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn golden(file: &str) -> PathBuf {
+    root().join("tests").join("golden").join(file)
+}
+
+fn check(name: &str) {
+    check_with(name, |_| ());
+}
+
+fn check_with(name: &str, configure: fn(&mut Config)) {
+    let code = fs::read_to_string(root().join("example").join(format!("{name}.bf"))).unwrap();
+    let input = fs::read(golden(&format!("{name}.in"))).unwrap_or_default();
+    let expected = fs::read(golden(&format!("{name}.out"))).unwrap();
+
+    let mut config = Config::new();
+    config.raw_code = code;
+    config.input_mode = InputMode::Raw;
+    configure(&mut config);
+    let mut output = Vec::new();
+    Interpreter::with_config(config)
+        .input(input.as_slice())
+        .output(&mut output)
+        .run()
+        .unwrap();
+    assert!(
+        output == expected,
+        "output of {name}.bf differs from {name}.out:\n{}",
+        String::from_utf8_lossy(&output)
+    );
+}
+
+#[test]
+fn every_example_has_a_golden_output() {
+    for entry in fs::read_dir(root().join("example")).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_stem().unwrap().to_str().unwrap();
+        assert!(
+            golden(&format!("{name}.out")).exists(),
+            "missing tests/golden/{name}.out"
+        );
+    }
+}
+
+#[test]
+fn calculator() {
+    check("calculator");
+}
+
+#[test]
+fn cellsize() {
+    check("cellsize");
+}
+
+#[test]
+fn char() {
+    check("char");
+}
+
+#[test]
+fn hanoi() {
+    check("hanoi");
+}
+
+#[test]
+#[ignore = "takes minutes without optimizations, run with `cargo test --release -- --ignored`"]
+fn mandel() {
+    check("mandel");
+}
+
+#[test]
+fn numwarp() {
+    check("numwarp");
+}
+
+#[test]
+fn oobrain() {
+    check("oobrain");
+}
+
+#[test]
+fn squares() {
+    check("squares");
+}
+
+#[test]
+fn triangle() {
+    check("triangle");
+}
+
+#[test]
+fn yapi() {
+    check("yapi");
+}
+
+/// Every example again through the JIT, mandel included since it only takes
+/// a moment there.
+#[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+#[test]
+fn examples_through_the_jit() {
+    for entry in fs::read_dir(root().join("example")).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_stem().unwrap().to_str().unwrap();
+        check_with(name, |config| config.jit = true);
+    }
+}
+
+/// Settings the JIT doesn't implement are errors rather than wrong output.
+#[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+#[test]
+fn the_jit_rejects_settings_it_does_not_implement() {
+    use brainfuck_hcy::{CellType, Overflow, Tape};
+
+    fn rejected(code: &str, configure: fn(&mut Config)) -> bool {
+        let mut config = Config::new();
+        config.raw_code = code.to_string();
+        config.jit = true;
+        configure(&mut config);
+        Interpreter::with_config(config).run().is_err()
+    }
+
+    assert!(rejected("<+.", |config| config.tape = Tape::Infinite));
+    assert!(rejected("-.", |config| {
+        config.cell_type = CellType::Big;
+        config.overflow = Overflow::Trap;
+    }));
+    assert!(rejected("-.", |config| config.cell_max = 100));
+}
@@ -0,0 +1,88 @@
+use brainfuck_hcy::{Cells, Config, Eof, Event, Machine, Tape};
+use std::time::Duration;
+
+/// Runs `machine` to the end, answering input requests from `input`.
+fn drive(machine: &mut Machine, input: &[u32]) -> Result<Vec<u32>, String> {
+    let mut input = input.iter();
+    let mut output = Vec::new();
+    loop {
+        match machine.run_until_event() {
+            Event::Output(n) => output.push(n),
+            Event::NeedInput => match input.next() {
+                Some(&n) => machine.provide_input(n).unwrap(),
+                None => machine.close_input().unwrap(),
+            },
+            Event::Halted => return Ok(output),
+            Event::Error(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+#[test]
+fn echoes_until_end_of_input() {
+    let mut machine = Machine::new(",[.,]").unwrap();
+    assert_eq!(drive(&mut machine, &[104, 105]), Ok(vec![104, 105]));
+    assert!(machine.halted());
+}
+
+#[test]
+fn repeats_need_input_until_answered() {
+    let mut machine = Machine::new(",.").unwrap();
+    assert!(matches!(machine.step(), Some(Event::NeedInput)));
+    assert!(matches!(machine.step(), Some(Event::NeedInput)));
+    machine.provide_input(7).unwrap();
+    assert!(matches!(machine.step(), Some(Event::Output(7))));
+    assert!(matches!(machine.step(), Some(Event::Halted)));
+}
+
+#[test]
+fn close_input_follows_the_eof_setting() {
+    let mut config = Config::new();
+    config.raw_code = "+,.".to_string();
+    config.eof = Eof::Unchanged;
+    let mut machine = Machine::with_config(config).unwrap();
+    assert_eq!(drive(&mut machine, &[]), Ok(vec![1]));
+}
+
+#[test]
+fn rejects_step_and_time_limits() {
+    let mut config = Config::new();
+    config.max_steps = Some(10);
+    assert!(Machine::with_config(config).is_err());
+    let mut config = Config::new();
+    config.timeout = Some(Duration::from_secs(1));
+    assert!(Machine::with_config(config).is_err());
+}
+
+#[test]
+fn steps_return_on_a_wrapping_tape_of_nonzero_cells() {
+    let mut config = Config::new();
+    config.raw_code = "+>+>+>+[>]".to_string();
+    config.tape = Tape::Wrap(4);
+    let mut machine = Machine::with_config(config).unwrap();
+    for _ in 0..100 {
+        assert!(machine.step().is_none());
+    }
+    assert!(!machine.halted());
+}
+
+#[test]
+fn rejects_input_that_does_not_fit_a_cell() {
+    let mut machine = Machine::new(",").unwrap();
+    assert!(machine.provide_input(1).is_err());
+    machine.run_until_event();
+    assert!(machine.provide_input(256).is_err());
+    machine.provide_input(255).unwrap();
+    assert!(matches!(machine.run_until_event(), Event::Halted));
+    assert_eq!(machine.state().tape, Cells::U32(vec![255]));
+}
+
+#[test]
+fn halts_on_errors() {
+    let mut machine = Machine::new("+\n<").unwrap();
+    match machine.run_until_event() {
+        Event::Error(e) => assert!(e.to_string().ends_with("at line 2:1"), "{e}"),
+        event => panic!("unexpected {event:?}"),
+    }
+    assert!(matches!(machine.run_until_event(), Event::Halted));
+}
@@ -0,0 +1,91 @@
+//! Checks that the optimizer doesn't change what a program does, by running
+//! it with and without `--no-opt`.
+
+use brainfuck_hcy::{CellType, Cells, Config, Interpreter, Overflow, Tape};
+
+/// The final tape, or whether the run failed, and the output.
+fn run(code: &str, optimize: bool, configure: fn(&mut Config)) -> (Option<Cells>, Vec<u8>) {
+    let mut config = Config::new();
+    config.raw_code = code.to_string();
+    config.optimize = optimize;
+    configure(&mut config);
+    let mut output = Vec::new();
+    let outcome = Interpreter::with_config(config)
+        .output(&mut output)
+        .run()
+        .ok();
+    (outcome.map(|outcome| outcome.tape), output)
+}
+
+/// The error a run stops with, if any.
+fn error(code: &str, optimize: bool, configure: fn(&mut Config)) -> Option<String> {
+    let mut config = Config::new();
+    config.raw_code = code.to_string();
+    config.optimize = optimize;
+    configure(&mut config);
+    Interpreter::with_config(config)
+        .output(&mut Vec::new())
+        .run()
+        .err()
+        .map(|e| e.to_string())
+}
+
+fn check(code: &str, configure: fn(&mut Config)) {
+    assert_eq!(
+        run(code, true, configure),
+        run(code, false, configure),
+        "{code} runs differently with --no-opt"
+    );
+}
+
+fn check_error(code: &str, configure: fn(&mut Config)) {
+    let message = error(code, false, configure);
+    assert!(message.is_some(), "{code} should fail with --no-opt");
+    assert_eq!(error(code, true, configure), message, "{code}");
+}
+
+#[test]
+fn multiply_loops_saturate_on_intermediate_values() {
+    let code = format!(">{}<+++[->++<>-<]>.", "+".repeat(254));
+    check(&code, |config| config.overflow = Overflow::Saturate);
+}
+
+#[test]
+fn multiply_loops_trap_on_intermediate_values() {
+    let code = format!(">{}<+++[->++<>-<]>.", "+".repeat(252));
+    check(&code, |config| config.overflow = Overflow::Trap);
+    let code = format!(">{}<+++[->++<]>.", "+".repeat(252));
+    check(&code, |config| config.overflow = Overflow::Trap);
+}
+
+#[test]
+fn big_cells_keep_loops_that_never_reach_zero() {
+    let big = |config: &mut Config| {
+        config.cell_type = CellType::Big;
+        config.max_steps = Some(10_000);
+    };
+    check(&format!("-[-]{}.", "+".repeat(49)), big);
+    check("-[->+<]", big);
+    check("+++[->++<]>.", big);
+}
+
+#[test]
+fn folded_moves_fail_where_they_would_without_folding() {
+    check_error("<>", |_| ());
+    check_error(">+.<<", |_| ());
+    let fixed = |config: &mut Config| config.tape = Tape::Fixed(2);
+    check_error(">>><<<", fixed);
+    check_error(">+>+<<", fixed);
+}
+
+#[test]
+fn scans_on_a_wrapping_tape_count_against_the_limits() {
+    check_error("+>+>+>+[>]", |config| {
+        config.tape = Tape::Wrap(4);
+        config.max_steps = Some(100);
+    });
+    check_error("+>+>+>+[<<]", |config| {
+        config.tape = Tape::Wrap(4);
+        config.max_steps = Some(100);
+    });
+}